@@ -1,6 +1,7 @@
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use glutin::event::{Event, WindowEvent, ElementState};
+use glutin::event::{Event, WindowEvent, ElementState, StartCause};
 use glutin::event_loop::{ControlFlow, EventLoop};
 use glutin::window::WindowBuilder;
 use glutin::ContextBuilder;
@@ -17,9 +18,10 @@ use faithful::model::{VertexModel, MeshModel};
 use faithful::tex_model::{TexModel, TexVertex};
 use faithful::view::*;
 
-use faithful::pop::level::{LevelPaths, GlobeTextureParams};
-use faithful::pop::objects::{Object3D, Vertex};
+use faithful::pop::level::{LevelPaths, GlobeTextureParams, read_level};
+use faithful::pop::objects::{Object3D, Vertex, MORPH_STEPS};
 use faithful::pop::bl320::make_bl320_texture_rgba;
+use faithful::pop::fs::open_resource_fs;
 
 use faithful::opengl::gl::{GlCtx, new_gl_ctx};
 use faithful::opengl::program::*;
@@ -67,6 +69,20 @@ fn mk_pop_envelope(gl: &GlCtx, mvp_model: &GlUniform1Cell<Matrix4::<f32>>, objec
     e
 }
 
+// Keeps position of previous model, returns original scale of new model
+fn replace_pop_envelope(gl: &GlCtx, mvp_model: &GlUniform1Cell<Matrix4::<f32>>, pop_obj: &mut ModelEnvelop<TexModel>, object: &Object3D, scale: f32) -> f32 {
+    let (l, a) = pop_obj.get(0).map(|m| (m.location, m.angles)).unwrap();
+    *pop_obj = mk_pop_envelope(gl, mvp_model, object);
+    let m = pop_obj.get(0).unwrap();
+    m.location = l;
+    m.angles = a;
+    let scale_origin = m.scale;
+    m.scale = scale_origin * scale;
+    scale_origin
+}
+
+const MORPH_FRAME_TIME: Duration = Duration::from_millis(80);
+
 /******************************************************************************/
 
 fn cli() -> Command {
@@ -76,13 +92,19 @@ fn cli() -> Command {
             .action(ArgAction::Set)
             .value_name("BASE_PATH")
             .value_parser(clap::value_parser!(PathBuf))
-            .help("Path to POP3 directory"),
+            .help("Path to POP3 directory or zip archive"),
         Arg::new("landtype")
             .long("landtype")
             .action(ArgAction::Set)
             .value_name("LAND_TYPE")
             .value_parser(clap::builder::StringValueParser::new())
             .help("Override land type"),
+        Arg::new("level")
+            .long("level")
+            .action(ArgAction::Set)
+            .value_name("LEVEL")
+            .value_parser(clap::value_parser!(u8).range(1..255))
+            .help("Take land type from level"),
         Arg::new("debug")
             .long("debug")
             .action(ArgAction::SetTrue)
@@ -106,7 +128,8 @@ fn main() {
         let base = matches.get_one("base").cloned();
         base.unwrap_or_else(|| Path::new("/opt/sandbox/pop").to_path_buf())
     };
-    let landtype = matches.get_one("landtype").cloned().unwrap_or_else(|| "1".to_string());
+    let landtype_opt: Option<String> = matches.get_one("landtype").cloned();
+    let level_num: Option<u8> = matches.get_one("level").copied();
     let debug = matches.get_flag("debug");
     let obj_num: Option<u16> = matches.get_one("obj_num").copied();
 
@@ -120,14 +143,44 @@ fn main() {
         .write_style_or("F_LOG_STYLE", "always");
     env_logger::init_from_env(env);
 
+    let fs = match open_resource_fs(&base) {
+        Ok(fs) => fs,
+        Err(e) => {
+            log::error!("Cannot open {base:?}: {e}");
+            return;
+        }
+    };
+
+    let landtype = match (landtype_opt, level_num) {
+        (Some(landtype), _) => landtype,
+        (None, Some(num)) => match read_level(&fs, Path::new("levels"), num) {
+            Ok((_, landtype)) => landtype,
+            Err(e) => {
+                log::error!("Cannot read level {num}: {e}");
+                return;
+            }
+        },
+        (None, None) => "1".to_string(),
+    };
+
     let (level_paths, params) = {
-        let data_dir = base.join("data");
-        let paths = LevelPaths::from_base(&data_dir, &landtype);
-        let params = GlobeTextureParams::from_level(&paths);
-        (paths, params)
+        let paths = LevelPaths::from_default_dir(&landtype);
+        match GlobeTextureParams::from_level(&fs, &paths) {
+            Ok(params) => (paths, params),
+            Err(e) => {
+                log::error!("Cannot load level resources: {e}");
+                return;
+            }
+        }
     };
 
-    let objects_3d = Object3D::from_file(&base, "0");
+    let objects_3d = match Object3D::from_fs(&fs, "0") {
+        Ok(objects_3d) => objects_3d,
+        Err(e) => {
+            log::error!("Cannot load objects: {e}");
+            return;
+        }
+    };
  
     //
 
@@ -153,7 +206,13 @@ fn main() {
     program_objects.use_program();
 
     let _bl320_tex = {
-        let (width, height, bl320_tex) = make_bl320_texture_rgba(&level_paths.bl320, &params.palette);
+        let (width, height, bl320_tex) = match make_bl320_texture_rgba(&fs, &level_paths.bl320, &params.palette) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("Cannot load BL320 texture: {e}");
+                return;
+            }
+        };
         log::debug!("Texture {width:?}/{height:?}");
         {
             let params = TextureParams{target: GL_TEXTURE_2D, internal_format: GL_RGBA32F, format: GL_RGBA, data_type: GL_UNSIGNED_BYTE, nearest: false};
@@ -185,12 +244,26 @@ fn main() {
     let mut do_render = true;
     let mut scale = 1.0;
     let mut scale_origin = pop_obj.get(0).map(|m| m.scale).unwrap();
+    let mut morph_play = false;
+    let mut morph_step: usize = 0;
+    let mut morph_forward = true;
+    let mut morph_next = Instant::now();
     el.run(move |event, _, control_flow| {
         log::trace!("{:?}", event);
         *control_flow = ControlFlow::Wait;
 
         match event {
             Event::LoopDestroyed => (),
+            Event::NewEvents(StartCause::ResumeTimeReached{..}) if morph_play => {
+                if (morph_forward && morph_step >= MORPH_STEPS) || (!morph_forward && morph_step == 0) {
+                    morph_forward = !morph_forward;
+                }
+                morph_step = if morph_forward { morph_step + 1 } else { morph_step - 1 };
+                morph_next = Instant::now() + MORPH_FRAME_TIME;
+                let object = objects_3d[obj_num].morphed(morph_step, MORPH_STEPS);
+                scale_origin = replace_pop_envelope(&gl, &mvp_model, &mut pop_obj, &object, scale);
+                do_render = true;
+            },
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::Resized(physical_size) => {
                     screen.width = physical_size.width;
@@ -247,18 +320,23 @@ fn main() {
                             VKC::R => {
                                 scale = 1.0;
                             },
+                            VKC::P => {
+                                // Experimental, morph0 layout is not verified
+                                if objects_3d[obj_num].morph().is_some() {
+                                    morph_play = !morph_play;
+                                    morph_next = Instant::now() + MORPH_FRAME_TIME;
+                                } else {
+                                    log::info!("Object {obj_num:?} has no morph");
+                                }
+                            },
                             _ => (),
                         }
                         if obj_num_new != obj_num {
                             obj_num = obj_num_new;
-                            let (l, a) = pop_obj.get(0).map(|m| (m.location, m.angles)).unwrap();
-                            pop_obj = mk_pop_envelope(&gl, &mvp_model, &objects_3d[obj_num]);
-                            if let Some(m) = pop_obj.get(0) {
-                                m.location = l;
-                                m.angles = a;
-                                scale_origin = m.scale;
-                                m.scale = scale_origin * scale;
-                            }
+                            morph_play = false;
+                            morph_step = 0;
+                            morph_forward = true;
+                            scale_origin = replace_pop_envelope(&gl, &mvp_model, &mut pop_obj, &objects_3d[obj_num], scale);
                         }
                         if let Some(m) = pop_obj.get(0) {
                             m.location[0] += loc[0];
@@ -280,6 +358,9 @@ fn main() {
             }
             _ => (),
         }
+        if morph_play {
+            *control_flow = ControlFlow::WaitUntil(morph_next);
+        }
         if do_render {
             unsafe {
                 gl.Enable(GL_DEPTH_TEST);
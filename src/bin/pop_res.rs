@@ -1,14 +1,16 @@
 use std::io::{Cursor, Write};
 use std::path::{Path, PathBuf};
-use std::fs::File;
-use std::io::Read;
 use std::collections::HashSet;
 
-use image::{RgbImage, RgbaImage, Rgb, GrayImage, ImageFormat, ImageOutputFormat, ImageBuffer, DynamicImage};
+use image::{RgbImage, RgbaImage, Rgb, GrayImage, ImageFormat, ImageOutputFormat, ImageBuffer, DynamicImage, Frame, Delay};
+use image::codecs::gif::{GifEncoder, Repeat};
 use clap::{arg, Arg, ArgAction, Command};
 
-use faithful::pop::level::{GlobeTextureParams, LevelPaths, LevelRes, ObjectPaths, read_pal};
-use faithful::pop::psfb::ContainerPSFB;
+use faithful::pop::{Error, Result};
+use faithful::pop::fs::{ResourceFs, open_resource_fs, read_resource_or_file};
+use faithful::pop::level::{GlobeTextureParams, LevelCatalog, LevelHeader, LevelPaths, LevelRes, ObjectPaths, LEVEL_TRIBES_OFFSET, LEVEL_UNITS_OFFSET, Sunlight, read_pal};
+use faithful::pop::psfb::{ContainerPSFB, quantize_rgba};
+use faithful::pop::palette::{Palette, RemapTable};
 use faithful::pop::landscape::common::{LandPos, LandscapeFull};
 use faithful::pop::landscape::minimap::texture_minimap;
 use faithful::pop::landscape::globe::texture_globe;
@@ -17,11 +19,16 @@ use faithful::pop::landscape::disp::texture_bigf0;
 use faithful::pop::landscape::water::texture_water;
 use faithful::pop::pls::decode;
 use faithful::pop::bl320::{read_bl320, read_bl160};
-use faithful::pop::types::{BinDeserializer, Image, AllocatorIter, ULCentreComposer, URCentreComposer, LayeredStorageSource, LayerComposer};
-use faithful::pop::types::{ImageInfo, ImageArea};
-use faithful::pop::types::{image_allocator_1d_horizontal, image_allocator_1d_vertical, image_allocator_2d};
-use faithful::pop::objects::{ObjectRaw, Shape, PointRaw, FaceRaw};
-use faithful::pop::animation::{AnimationsData, AnimationSequence, AnimationFrame};
+use faithful::pop::types::{BinDeserializer, BinSerializer, Record, Image, AllocatorIter, ULCentreComposer, URCentreComposer, LayeredStorageSource, LayerComposer};
+use faithful::pop::types::{ImageInfo, ImagePos, ImageArea};
+use faithful::pop::types::{image_allocator_1d_horizontal, image_allocator_1d_vertical, blend_image_allocator_2d, PixelOp};
+use faithful::pop::objects::{Object3D, ObjectRaw, Shape, PointRaw, FaceRaw, MORPH_STEPS};
+use faithful::pop::wavefront::{write_obj, write_mtl};
+use faithful::pop::gltf::{GltfObject, write_glb, grid_layout};
+use faithful::pop::json::json_string;
+use faithful::pop::footprint::Footprint;
+use faithful::pop::units::Unit;
+use faithful::pop::animation::{AnimationsData, AnimationSequence, AnimationFrame, AnimationElement};
 
 /******************************************************************************/
 
@@ -31,12 +38,11 @@ const DEFAULT_BASE_PATH: &str = "/opt/sandbox/pop";
 type PaletteArray = [(u8, u8, u8); 256];
 type FramesSet = HashSet<usize>;
 
-fn draw_palette(pal: &[u8], width: u32, height: u32, num_colors: u32) -> RgbImage {
+fn draw_palette(pal: &Palette, width: u32, height: u32, num_colors: u32) -> RgbImage {
     let mut img = RgbImage::new(width, height);
     let color_height = height / num_colors;
     for c in 0..num_colors {
-        let palette_index = (c * 4) as usize;
-        let buf: &[u8] = &pal[palette_index..(palette_index+3)];
+        let buf = pal.rgb(c as u8);
         let si = c * color_height;
         for i in 0..color_height {
             for j in 0..width {
@@ -65,20 +71,16 @@ fn make_disp_texture2(params: &GlobeTextureParams) -> RgbImage {
     img
 }
 
-fn decode_pls(pls_path: &Path) -> Vec<u8> {
-    let mut file = File::options().read(true).open(pls_path).unwrap();
-    let mut data = Vec::new();
-    let _file_size = file.read_to_end(&mut data);
+fn decode_pls(fs: &dyn ResourceFs, pls_path: &Path) -> Result<Vec<u8>> {
+    let mut data = read_resource_or_file(fs, pls_path)?;
     decode(&mut data);
-    data
+    Ok(data)
 }
 
-#[allow(clippy::needless_range_loop)]
-fn seq_to_pal(pal: &[u8]) -> PaletteArray {
+fn pal_array(pal: &Palette) -> PaletteArray {
     let mut pal_tup = [(0u8, 0u8, 0u8); 256];
-    for i in 0..255 {
-        let pi = i * 4;
-        pal_tup[i] = (pal[pi], pal[pi+1], pal[pi+2]);
+    for (t, [r, g, b]) in pal_tup.iter_mut().zip(pal.colors()) {
+        *t = (r, g, b);
     }
     pal_tup
 }
@@ -89,9 +91,9 @@ fn image_to_gray(sprite: Image) -> GrayImage {
     GrayImage::from_raw(width as u32, height as u32, sprite.data).unwrap()
 }
 
-fn draw_image_pal(pal: &[u8], image: Image) -> RgbaImage {
+fn draw_image_pal(pal: &Palette, image: Image) -> RgbaImage {
     let img = image_to_gray(image);
-    let pal_tup = seq_to_pal(pal);
+    let pal_tup = pal_array(pal);
     img.expand_palette(&pal_tup, None)
 }
 
@@ -103,12 +105,12 @@ fn draw_image(palette: &Option<PaletteArray>, img: Image) -> DynamicImage {
     DynamicImage::ImageLuma8(img)
 }
 
-fn draw_sprites(psfb: &ContainerPSFB, start: usize, num: usize, prefix: &Path, palette: &Option<PaletteArray>) {
+fn draw_sprites(psfb: &ContainerPSFB, start: usize, num: usize, prefix: &Path, palette: &Option<PaletteArray>, op: PixelOp) -> Result<()> {
     if start >= num {
-        return;
+        return Ok(());
     }
     for i in start..(start+num) {
-        if let Some(sprite) = psfb.get_image(i as usize) {
+        if let Some(sprite) = psfb.get_image_op(i as usize, op)? {
             let name = format!("{:}_{:?}.bmp", prefix.to_str().unwrap(), i);
             println!("{}", name);
             let path = Path::new(&name);
@@ -116,22 +118,119 @@ fn draw_sprites(psfb: &ContainerPSFB, start: usize, num: usize, prefix: &Path, p
             img.save_with_format(&path, ImageFormat::Bmp).unwrap();
         }
     }
+    Ok(())
 }
 
-fn draw_sprites_img(psfb: &ContainerPSFB, start: usize, num: usize, palette: &Option<PaletteArray>) -> DynamicImage {
-    let allocator = image_allocator_2d(1500);
+fn draw_sprites_img(psfb: &ContainerPSFB, start: usize, num: usize, palette: &Option<PaletteArray>, op: PixelOp) -> Result<(DynamicImage, Vec<AtlasEntry>)> {
+    let allocator = blend_image_allocator_2d(1500, op);
     let mut p = allocator.alloc_iter(&mut psfb.sprites_info().iter());
+    let mut drawn = Vec::new();
     for i in start..(start+num) {
-        psfb.get_storage(i as usize, &mut p);
+        if psfb.get_storage(i, &mut p)? {
+            drawn.push(i);
+        }
+    }
+    let (image, placements) = p.get_image_placements();
+    let entries = drawn.into_iter().zip(placements).map(|(i, area)| {
+        let offset = psfb.sprites_info()[i].offset;
+        AtlasEntry{name: format!("sprite_{i}"), index: i, area, offset: Some(offset), anchor: (0, 0)}
+    }).collect();
+    Ok((draw_image(palette, image.image), entries))
+}
+
+/*
+ * Atlas descriptor is TexturePacker JSON (array) file, index of sprite or frame,
+ * offset in source file and anchor point in pixels are added to each frame.
+ */
+struct AtlasEntry {
+    name: String,
+    index: usize,
+    area: ImageArea,
+    offset: Option<usize>,
+    anchor: (isize, isize),
+}
+
+fn atlas_json(image_name: &str, width: usize, height: usize, entries: &[AtlasEntry]) -> String {
+    let frames: Vec<String> = entries.iter().map(|e| {
+        let (x, y, w, h) = (e.area.pos_x(), e.area.pos_y(), e.area.width(), e.area.height());
+        let (ax, ay) = e.anchor;
+        let pivot_x = if w > 0 { ax as f32 / w as f32 } else { 0.0 };
+        let pivot_y = if h > 0 { ay as f32 / h as f32 } else { 0.0 };
+        let mut s = format!("    {{\"filename\": {}, \"index\": {}, \"frame\": {{\"x\": {x}, \"y\": {y}, \"w\": {w}, \"h\": {h}}}, \"rotated\": false, \"trimmed\": false, \"spriteSourceSize\": {{\"x\": 0, \"y\": 0, \"w\": {w}, \"h\": {h}}}, \"sourceSize\": {{\"w\": {w}, \"h\": {h}}}, \"anchor\": {{\"x\": {ax}, \"y\": {ay}}}, \"pivot\": {{\"x\": {pivot_x}, \"y\": {pivot_y}}}"
+                            , json_string(&e.name), e.index);
+        if let Some(offset) = e.offset {
+            s.push_str(&format!(", \"offset\": {offset}"));
+        }
+        s.push('}');
+        s
+    }).collect();
+    format!("{{\n  \"frames\": [\n{}\n  ],\n  \"meta\": {{\"image\": {}, \"format\": \"RGBA8888\", \"size\": {{\"w\": {width}, \"h\": {height}}}, \"scale\": \"1\"}}\n}}\n"
+            , frames.join(",\n"), json_string(image_name))
+}
+
+// Writes <path>.png and <path>.json
+fn write_atlas(path: &Path, img: &DynamicImage, entries: &[AtlasEntry]) -> Result<()> {
+    let png_path = path.with_extension("png");
+    let json_path = path.with_extension("json");
+    img.save_with_format(&png_path, ImageFormat::Png).map_err(|e| image_error(e, &png_path))?;
+    let image_name = png_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let json = atlas_json(&image_name, img.width() as usize, img.height() as usize, entries);
+    std::fs::write(&json_path, json).map_err(|e| Error::from(e).in_file(&json_path))?;
+    println!("{}", png_path.display());
+    println!("{}", json_path.display());
+    Ok(())
+}
+
+// base is file offset of the table which holds records
+fn print_record<T: Record>(base: usize, index: usize, record: &T) {
+    let base = base + index * T::SIZE;
+    println!("  {index:?}:");
+    for (field, value) in T::FIELDS.iter().zip(record.field_values()) {
+        println!("    0x{:06x} {} = {}", base + field.offset, field.name, value);
     }
-    let image = p.get_image();
-    draw_image(palette, image)
 }
 
-struct AnimationsConfig {
+fn print_records<T: Record + std::fmt::Debug>(records: &[T], base: usize, offsets: bool) {
+    for (index, record) in records.iter().enumerate() {
+        if offsets {
+            print_record(base, index, record);
+        } else {
+            println!("  {:?}", record);
+        }
+    }
+}
+
+fn print_header(header: &LevelHeader) {
+    let raw = &header.raw;
+    println!("Name = {:?}", header.name());
+    match header.landscape_type() {
+        Ok(t) => println!("Landscape type = {} ({})", raw.landscape_type, t),
+        Err(e) => println!("Landscape type = {} ({})", raw.landscape_type, e),
+    }
+    println!("Num players = {}", raw.num_players);
+    println!("Computer players = {:?}", raw.computer_players);
+    println!("Allies = {:?}", raw.allies);
+    println!("Objects bank = {}", raw.objects_bank);
+    println!("Flags = 0x{:02x}", raw.flags);
+    println!("Start pos = 0x{:04x}, angle = {}", raw.start_pos, raw.start_angle);
+    for (index, marker) in raw.markers.iter().enumerate() {
+        if *marker != 0 {
+            println!("  Marker {index:?} = 0x{marker:04x}");
+        }
+    }
+    println!("Unknown = {:?}", raw.unknown);
+    if !header.trailing.is_empty() {
+        println!("Trailing = {:?}", header.trailing);
+    }
+}
+
+struct AnimationsConfig<'a> {
     img_size: usize,
     with_tribe: bool,
     with_type: bool,
+    remap: RemapTable,
+    // Op of all layers instead of tribe remap, e.g. fade or ghost blending
+    layer_op: Option<PixelOp<'a>>,
 }
 
 fn draw_anim_frames<L>(anim_seq: &Vec<AnimationSequence>
@@ -140,9 +239,9 @@ fn draw_anim_frames<L>(anim_seq: &Vec<AnimationSequence>
                       , frames_set: &FramesSet
                       , composer: &L
                       , config: &AnimationsConfig
-                      ) -> DynamicImage
+                      ) -> Result<(DynamicImage, Vec<AtlasEntry>)>
     where L: LayerComposer<ComposerResult=ImageArea> {
-    let allocator = image_allocator_2d(config.img_size);
+    let allocator = blend_image_allocator_2d(config.img_size, PixelOp::Remap(&config.remap));
     let frames = {
         let seq = AnimationSequence::get_frames(anim_seq);
         if !frames_set.is_empty() {
@@ -163,18 +262,244 @@ fn draw_anim_frames<L>(anim_seq: &Vec<AnimationSequence>
     }).collect();
     let mut p = allocator.alloc_iter(&mut composed_sprites.iter());
     let mut cs_iter = composed_sprites.iter();
+    // name, frame index and anchor of drawn frames
+    let mut drawn = Vec::new();
     for frame in &frames {
         let pr = frame.get_permutations(config.with_tribe, config.with_type);
-        for (elems, i) in pr.into_iter().zip(&mut cs_iter) {
+        for (n, (elems, i)) in pr.into_iter().zip(&mut cs_iter).enumerate() {
             let img_area = ImageArea::from_image_pos(i);
-            let mut ulc = LayeredStorageSource::new(&mut p, img_area, elems.iter().map(|im| (im.coord_x as isize, im.coord_y as isize)), composer);
+            let mut ulc = LayeredStorageSource::new(&mut p, img_area, elems.iter().copied(), composer);
+            if let Some(op) = config.layer_op {
+                ulc.set_pixel_op(op);
+            }
             for elem in &elems {
-                psfb.get_storage(elem.sprite_index, &mut ulc);
+                psfb.get_storage(elem.sprite_index, &mut ulc)?;
+            }
+            drawn.push((format!("frame_{}_{n}", frame.index), frame.index, (i.pos_x(), i.pos_y())));
+        }
+    }
+    let (image, placements) = p.get_image_placements();
+    let entries = drawn.into_iter().zip(placements).map(|((name, index, anchor), area)| {
+        AtlasEntry{name, index, area, offset: None, anchor}
+    }).collect();
+    Ok((draw_image(palette, image.image), entries))
+}
+
+/*
+ * Frames of sequence are drawn on the same canvas which fits all of them,
+ * only first permutation of tribe/type sprites is used.
+ */
+fn draw_anim_sequence<L>(anim_seq: &AnimationSequence
+                        , psfb: &ContainerPSFB
+                        , composer: &L
+                        , config: &AnimationsConfig
+                        ) -> Result<Vec<Image>>
+    where L: LayerComposer<ComposerResult=ImageArea> {
+    let frames_elems: Vec<Vec<AnimationElement>> = anim_seq.frames.iter().map(|frame| {
+        let elems = frame.get_permutations(config.with_tribe, config.with_type).into_iter().next().unwrap_or_default();
+        elems.into_iter().filter(|elem| psfb.get_info(elem.sprite_index).is_some()).collect()
+    }).collect();
+    let areas: Vec<ImageArea> = frames_elems.iter().flatten().filter_map(|sprite| {
+        psfb.get_info(sprite.sprite_index).map(|im| {
+            ImageArea::from_image(&im, sprite.coord_x as isize, sprite.coord_y as isize)
+        })
+    }).collect();
+    let canvas = composer.compose_layers(&mut areas.iter());
+    let mut images = Vec::<Image>::with_capacity(frames_elems.len());
+    for elems in &frames_elems {
+        let img_area = ImageArea::from_image_pos(&canvas);
+        let mut ulc = LayeredStorageSource::new(&mut images, img_area, elems.iter().copied(), composer);
+        for elem in elems {
+            psfb.get_storage(elem.sprite_index, &mut ulc)?;
+        }
+    }
+    Ok(images)
+}
+
+fn write_anim_gif(path: &Path, frames: Vec<Image>, palette: &Option<PaletteArray>, delay_ms: u32) -> Result<()> {
+    let file = std::fs::File::create(path).map_err(|e| Error::from(e).in_file(path))?;
+    let mut encoder = GifEncoder::new(file);
+    encoder.set_repeat(Repeat::Infinite).map_err(|e| image_error(e, path))?;
+    let frames = frames.into_iter().map(|frame| {
+        let img = image_to_gray(frame);
+        // index 0 is transparent
+        let rgba = match palette {
+            Some(p) => img.expand_palette(p, Some(0)),
+            None => DynamicImage::ImageLuma8(img).to_rgba8(),
+        };
+        Frame::from_parts(rgba, 0, 0, Delay::from_numer_denom_ms(delay_ms, 1))
+    });
+    encoder.encode_frames(frames).map_err(|e| image_error(e, path))
+}
+
+fn write_anim_apng(path: &Path, frames: Vec<Image>, palette: &Option<PaletteArray>, delay_ms: u32) -> Result<()> {
+    let png_error = |e: png::EncodingError| {
+        Error::from(std::io::Error::other(e.to_string())).in_file(path)
+    };
+    let (width, height) = match frames.first() {
+        Some(frame) => (frame.width as u32, frame.height as u32),
+        None => return Ok(()),
+    };
+    let file = std::fs::File::create(path).map_err(|e| Error::from(e).in_file(path))?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width, height);
+    encoder.set_depth(png::BitDepth::Eight);
+    match palette {
+        Some(p) => {
+            let pal_bytes: Vec<u8> = p.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_palette(pal_bytes);
+            encoder.set_trns(vec![0u8]);
+        },
+        None => encoder.set_color(png::ColorType::Grayscale),
+    }
+    encoder.set_animated(frames.len() as u32, 0).map_err(png_error)?;
+    encoder.set_frame_delay(delay_ms.min(u16::MAX as u32) as u16, 1000).map_err(png_error)?;
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    for frame in &frames {
+        writer.write_image_data(&frame.data).map_err(png_error)?;
+    }
+    writer.finish().map_err(png_error)
+}
+
+fn make_bl320_png(fs: &dyn ResourceFs, paths: &LevelPaths) -> Result<RgbaImage> {
+    let pal = read_pal(fs, paths)?;
+    let provider = read_bl320(fs, &image_allocator_1d_vertical(), &paths.bl320)?;
+    // palette index 0 is transparent as in sprites
+    Ok(image_to_gray(provider.get_image()).expand_palette(&pal_array(&pal), Some(0)))
+}
+
+// Colors of free, occupied, entrance and origin cells
+const FOOTPRINT_COLORS: [(u8, u8, u8); 4] = [(0x20, 0x20, 0x20), (0x80, 0x80, 0x80), (0x30, 0xc0, 0x30), (0xc0, 0x30, 0x30)];
+
+fn draw_footprint_orientations(footprint: &Footprint, cell_size: usize) -> RgbaImage {
+    let orientations: Vec<Image> = footprint.orientations().iter().map(|f| f.to_image(cell_size)).collect();
+    let gap = cell_size;
+    let width = orientations.iter().map(|img| img.width + gap).sum::<usize>().saturating_sub(gap).max(1);
+    let height = orientations.iter().map(|img| img.height).max().unwrap_or(0).max(1);
+    let mut img = RgbaImage::new(width as u32, height as u32);
+    let mut offset = 0;
+    for image in &orientations {
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let (r, g, b) = FOOTPRINT_COLORS[image.data[y * image.width + x] as usize];
+                img.put_pixel((offset + x) as u32, y as u32, image::Rgba([r, g, b, 0xff]));
             }
         }
+        offset += image.width + gap;
     }
-    let image = p.get_image();
-    draw_image(palette, image)
+    img
+}
+
+fn draw_footprints(fs: &dyn ResourceFs, matches: &clap::ArgMatches) -> Result<()> {
+    let bank_num = matches.get_one::<String>("num").expect("required");
+    let cell_size: usize = matches.get_one("cell").copied().expect("default");
+    let output: PathBuf = matches.get_one("output").cloned().unwrap();
+    let index: Option<usize> = matches.get_one("index").copied();
+    let paths = ObjectPaths::from_default_dir(bank_num);
+    let shapes = Shape::from_fs_vec(fs, &paths.shapes)?;
+    let selected: Vec<(usize, &Shape)> = match index {
+        Some(i) => match shapes.get(i) {
+            Some(shape) => vec![(i, shape)],
+            None => return Err(Error::OutOfRange{what: "shape", index: i, len: shapes.len()}),
+        },
+        None => shapes.iter().enumerate().collect(),
+    };
+    std::fs::create_dir_all(&output).map_err(|e| Error::from(e).in_file(&output))?;
+    for (i, shape) in selected {
+        let footprint = shape.footprint()?;
+        let img = draw_footprint_orientations(&footprint, cell_size);
+        let path = output.join(format!("shape-{bank_num}-{i:03}.png"));
+        img.save_with_format(&path, ImageFormat::Png).map_err(|e| image_error(e, &path))?;
+        println!("{} {}x{} origin={:?} entrance={:?}", path.display(), footprint.width(), footprint.height()
+                 , footprint.origin(), footprint.entrance());
+    }
+    Ok(())
+}
+
+/*
+ * OBJ objects are written to separate files sharing one material file and
+ * BL320 texture atlas. glb files embed the texture, with --grid all objects
+ * are written to one scene.
+ */
+fn export_objects(fs: &dyn ResourceFs, matches: &clap::ArgMatches) -> Result<()> {
+    let bank_num = matches.get_one::<String>("num").expect("required");
+    let landtype = matches.get_one::<String>("landtype").expect("default");
+    let format = matches.get_one::<String>("format").expect("default");
+    let output: PathBuf = matches.get_one("output").cloned().unwrap();
+    let index: Option<usize> = matches.get_one("index").copied();
+    let objects = Object3D::from_fs(fs, bank_num)?;
+    let selected: Vec<(usize, &Object3D)> = match index {
+        Some(i) => match objects.iter().find(|object| object.index() == i) {
+            Some(object) => vec![(i, object)],
+            None => return Err(Error::BadFormat(format!("object {i} does not exist or has no faces"))),
+        },
+        None => objects.iter().map(|object| (object.index(), object)).collect(),
+    };
+    std::fs::create_dir_all(&output).map_err(|e| Error::from(e).in_file(&output))?;
+
+    let paths = LevelPaths::from_default_dir(landtype);
+    let texture = make_bl320_png(fs, &paths)?;
+    if format == "glb" {
+        let mut png = Vec::new();
+        texture.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png).map_err(|e| image_error(e, &paths.bl320))?;
+        let write_scene = |name: &str, objects: &[GltfObject]| -> Result<()> {
+            let path = output.join(format!("{name}.glb"));
+            let mut glb = Vec::new();
+            write_glb(&mut glb, objects, Some(&png))?;
+            std::fs::write(&path, glb).map_err(|e| Error::from(e).in_file(&path))?;
+            println!("{}", path.display());
+            Ok(())
+        };
+        if matches.get_flag("grid") {
+            let layout = grid_layout(&selected.iter().map(|(_, object)| *object).collect::<Vec<&Object3D>>());
+            let objects: Vec<GltfObject> = selected.iter().zip(layout).map(|((i, object), translation)| {
+                GltfObject{name: format!("object-{bank_num}-{i:03}"), object, translation}
+            }).collect();
+            write_scene(&format!("objects-{bank_num}"), &objects)?;
+        } else {
+            for (i, object) in selected {
+                let name = format!("object-{bank_num}-{i:03}");
+                write_scene(&name, &[GltfObject{name: name.clone(), object, translation: [0.0; 3]}])?;
+            }
+        }
+        return Ok(());
+    }
+
+    let texture_name = format!("bl320-{landtype}.png");
+    let texture_path = output.join(&texture_name);
+    texture.save_with_format(&texture_path, ImageFormat::Png).map_err(|e| image_error(e, &texture_path))?;
+    println!("{}", texture_path.display());
+
+    let mtl_name = format!("objects-{bank_num}.mtl");
+    let mtl_path = output.join(&mtl_name);
+    let mut mtl = Vec::new();
+    write_mtl(&mut mtl, &texture_name)?;
+    std::fs::write(&mtl_path, mtl).map_err(|e| Error::from(e).in_file(&mtl_path))?;
+    println!("{}", mtl_path.display());
+
+    let write_obj_file = |name: &str, object: &Object3D| -> Result<()> {
+        let path = output.join(format!("{name}.obj"));
+        let mut obj = Vec::new();
+        write_obj(&mut obj, name, object, &mtl_name)?;
+        std::fs::write(&path, obj).map_err(|e| Error::from(e).in_file(&path))?;
+        println!("{}", path.display());
+        Ok(())
+    };
+    let morph_steps: Option<usize> = if matches.contains_id("morph") {
+        Some(matches.get_one("morph").copied().unwrap_or(MORPH_STEPS))
+    } else {
+        None
+    };
+    for (i, object) in selected {
+        let name = format!("object-{bank_num}-{i:03}");
+        write_obj_file(&name, object)?;
+        if let (Some(steps), Some(_)) = (morph_steps, object.morph()) {
+            for step in 0..=steps {
+                write_obj_file(&format!("{name}-m{step:02}"), &object.morphed(step, steps))?;
+            }
+        }
+    }
+    Ok(())
 }
 
 /*
@@ -185,21 +510,16 @@ fn draw_anim_frames<L>(anim_seq: &Vec<AnimationSequence>
  *  --move [v; h] -- move maps centre
  *  --base <path> -- base path to pop3
  *  --land <type> -- override landscape texturing type
+ *  --sunlight <v1,v2,v3> -- override level sunlight
  */
 
-fn default_args() -> [Arg; 3] {
+fn default_args() -> [Arg; 2] {
     [
         Arg::new("move")
             .long("move")
             .action(ArgAction::Set)
             .value_name("POSITION")
             .help("Move texture centre"),
-        Arg::new("base")
-            .long("base")
-            .action(ArgAction::Set)
-            .value_name("BASE_PATH")
-            .value_parser(clap::value_parser!(PathBuf))
-            .help("Path to pop3 directory"),
         Arg::new("landtype")
             .long("landtype")
             .action(ArgAction::Set)
@@ -209,6 +529,47 @@ fn default_args() -> [Arg; 3] {
     ]
 }
 
+fn sunlight_arg() -> Arg {
+    Arg::new("sunlight")
+        .long("sunlight")
+        .action(ArgAction::Set)
+        .value_name("V1,V2,V3")
+        .value_parser(parse_sunlight)
+        .help("Override level sunlight")
+}
+
+fn remap_arg() -> Arg {
+    Arg::new("remap")
+        .long("remap")
+        .action(ArgAction::Set)
+        .value_name("PATH")
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("Remap colours with 256 byte table of palette indices, e.g. to colours of other tribe")
+}
+
+fn atlas_arg() -> Arg {
+    Arg::new("atlas")
+        .long("atlas")
+        .action(ArgAction::Set)
+        .value_name("PATH")
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("Write atlas PATH.png and its descriptor PATH.json")
+}
+
+fn read_remap(fs: &dyn ResourceFs, matches: &clap::ArgMatches) -> Result<RemapTable> {
+    match matches.get_one::<PathBuf>("remap") {
+        Some(path) => RemapTable::from_bytes(&read_resource_or_file(fs, path)?).map_err(|e| e.in_file(path)),
+        None => Ok(RemapTable::identity()),
+    }
+}
+
+fn offsets_arg() -> Arg {
+    Arg::new("offsets")
+        .long("offsets")
+        .action(ArgAction::SetTrue)
+        .help("Print raw records field by field with file offsets")
+}
+
 fn cli() -> Command {
     let args = default_args();
     Command::new("pop_res")
@@ -216,11 +577,21 @@ fn cli() -> Command {
         .subcommand_required(true)
         .arg_required_else_help(true)
         .allow_external_subcommands(true)
+        .arg(
+            Arg::new("base")
+                .long("base")
+                .global(true)
+                .action(ArgAction::Set)
+                .value_name("BASE_PATH")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Path to pop3 directory or zip archive"),
+        )
         .subcommand(
             Command::new("globe")
                 .about("Create globe texture image")
                 .arg(arg!(<num> "Level number"))
                 .args(&args)
+                .arg(sunlight_arg())
                 .arg_required_else_help(true),
         )
         .subcommand(
@@ -228,6 +599,7 @@ fn cli() -> Command {
                 .about("Create full land texture image")
                 .arg(arg!(<num> "Level number"))
                 .args(&args)
+                .arg(sunlight_arg())
                 .arg_required_else_help(true),
         )
         .subcommand(
@@ -235,6 +607,13 @@ fn cli() -> Command {
                 .about("Create minimap texture image")
                 .arg(arg!(<num> "Level number"))
                 .args(&args)
+                .arg(sunlight_arg())
+                .arg(
+                    Arg::new("fog")
+                        .long("fog")
+                        .action(ArgAction::SetTrue)
+                        .help("Hide cells which are not marked as visible in land flags"),
+                )
                 .arg_required_else_help(true),
         )
         .subcommand(
@@ -271,21 +650,127 @@ fn cli() -> Command {
         )
         .subcommand(
             Command::new("palette")
-                .about("Create palette texture image")
-                .arg(arg!(<num> "Level number"))
-                .arg_required_else_help(true),
+                .about("Convert palette of a level, fenew or a palette file")
+                .arg(arg!([num] "Level number"))
+                .args([
+                    Arg::new("fenew")
+                        .long("fenew")
+                        .action(ArgAction::SetTrue)
+                        .help("Use frontend palette (fenew/fepal0.dat)"),
+                    Arg::new("input")
+                        .long("input")
+                        .action(ArgAction::Set)
+                        .value_name("PALETTE_PATH")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Read palette file (pop3, .pal, .gpl or .png swatch)"),
+                    Arg::new("format")
+                        .long("format")
+                        .action(ArgAction::Set)
+                        .value_name("FORMAT")
+                        .value_parser(["bmp", "dat", "jasc", "gpl", "png"])
+                        .default_value("bmp")
+                        .help("Output format"),
+                ]),
         )
         .subcommand(
             Command::new("objects")
                 .about("Objects commands")
                 .arg(arg!(<num> "Bank num"))
+                .arg(offsets_arg())
+                .args_conflicts_with_subcommands(true)
+                .subcommand_negates_reqs(true)
+                .subcommand(
+                    Command::new("export")
+                        .about("Export objects of bank as 3D models with BL320 texture atlas")
+                        .arg(arg!(<num> "Bank num"))
+                        .args([
+                            Arg::new("format")
+                                .long("format")
+                                .action(ArgAction::Set)
+                                .value_name("FORMAT")
+                                .value_parser(["obj", "glb"])
+                                .default_value("obj")
+                                .help("Output format"),
+                            Arg::new("morph")
+                                .long("morph")
+                                .action(ArgAction::Set)
+                                .num_args(0..=1)
+                                .value_name("STEPS")
+                                .value_parser(clap::value_parser!(usize))
+                                .help("Also write morph frames of objects as OBJ files, 8 steps by default (experimental, morph0 layout is not verified)"),
+                            Arg::new("grid")
+                                .long("grid")
+                                .action(ArgAction::SetTrue)
+                                .help("Write all objects to one glb scene laid out in a grid"),
+                            Arg::new("index")
+                                .long("index")
+                                .action(ArgAction::Set)
+                                .value_name("INDEX")
+                                .value_parser(clap::value_parser!(usize))
+                                .help("Export only object with index in OBJS0 file"),
+                            Arg::new("landtype")
+                                .long("landtype")
+                                .action(ArgAction::Set)
+                                .value_name("LAND_TYPE")
+                                .value_parser(clap::builder::StringValueParser::new())
+                                .default_value("1")
+                                .help("Land type of BL320 texture and palette"),
+                            Arg::new("output")
+                                .long("output")
+                                .required(true)
+                                .action(ArgAction::Set)
+                                .value_name("DIR")
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .help("Output directory"),
+                        ])
+                        .arg_required_else_help(true),
+                )
+                .subcommand(
+                    Command::new("footprints")
+                        .about("Draw building footprints of SHAPES.DAT in 4 orientations")
+                        .arg(arg!(<num> "Bank num"))
+                        .args([
+                            Arg::new("index")
+                                .long("index")
+                                .action(ArgAction::Set)
+                                .value_name("INDEX")
+                                .value_parser(clap::value_parser!(usize))
+                                .help("Draw only shape with index"),
+                            Arg::new("cell")
+                                .long("cell")
+                                .action(ArgAction::Set)
+                                .value_name("SIZE")
+                                .value_parser(clap::value_parser!(usize))
+                                .default_value("16")
+                                .help("Size of footprint cell in pixels"),
+                            Arg::new("output")
+                                .long("output")
+                                .required(true)
+                                .action(ArgAction::Set)
+                                .value_name("DIR")
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .help("Output directory"),
+                        ])
+                        .arg_required_else_help(true),
+                )
                 .arg_required_else_help(true),
         )
+        .subcommand(
+            Command::new("header")
+                .about("Print level header")
+                .arg(arg!(<num> "Level number"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("levels")
+                .about("List levels found in levels directory")
+        )
         .subcommand(
             Command::new("units")
                 .about("Units commands")
                 .arg(arg!(<num> "Level number"))
                 .args(&args)
+                .arg(offsets_arg())
                 .arg_required_else_help(true),
         )
         .subcommand(
@@ -298,6 +783,7 @@ fn cli() -> Command {
                         .value_name("FILE_PATH")
                         .value_parser(clap::value_parser!(PathBuf))
                         .help("Path to PSFB file"),
+                    offsets_arg(),
                 ]).arg_required_else_help(true),
         )
         .subcommand(
@@ -336,6 +822,87 @@ fn cli() -> Command {
                         .long("no_type")
                         .action(ArgAction::SetTrue)
                         .help("Do not show type images"),
+                    Arg::new("fade")
+                        .long("fade")
+                        .action(ArgAction::Set)
+                        .value_name("LEVEL")
+                        .value_parser(clap::value_parser!(usize))
+                        .conflicts_with_all(["remap", "ghost"])
+                        .help("Draw frames with brightness level of fade table"),
+                    Arg::new("ghost")
+                        .long("ghost")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("remap")
+                        .help("Blend layers of frames over each other with ghost table"),
+                    Arg::new("landtype")
+                        .long("landtype")
+                        .action(ArgAction::Set)
+                        .value_name("LAND_TYPE")
+                        .value_parser(clap::builder::StringValueParser::new())
+                        .default_value("1")
+                        .help("Land type of fade and ghost tables"),
+                    remap_arg(),
+                    atlas_arg(),
+                ]).arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("anims_export")
+                .about("Export animation sequences as animated images, one file per sequence")
+                .args([
+                    Arg::new("path")
+                        .long("path")
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .value_name("FILE_PATH")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Path to PSFB file"),
+                    Arg::new("palette")
+                        .long("palette")
+                        .action(ArgAction::Set)
+                        .value_name("PALETTE_PATH")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Path to palette file"),
+                    Arg::new("output")
+                        .long("output")
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .value_name("DIR")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Output directory"),
+                    Arg::new("seq")
+                        .long("seq")
+                        .action(ArgAction::Set)
+                        .value_name("IDS")
+                        .value_parser(clap::builder::StringValueParser::new())
+                        .help("Sequences ids, all sequences are exported by default"),
+                    Arg::new("format")
+                        .long("format")
+                        .action(ArgAction::Set)
+                        .value_name("FORMAT")
+                        .value_parser(["gif", "apng"])
+                        .default_value("gif")
+                        .help("Output format"),
+                    Arg::new("delay")
+                        .long("delay")
+                        .action(ArgAction::Set)
+                        .value_name("MS")
+                        .value_parser(clap::value_parser!(u32))
+                        .default_value("100")
+                        .help("Delay between frames in milliseconds"),
+                    Arg::new("composer")
+                        .long("composer")
+                        .action(ArgAction::Set)
+                        .value_name("COMPOSER")
+                        .value_parser(clap::builder::StringValueParser::new())
+                        .help("Composer type"),
+                    Arg::new("no_tribe")
+                        .long("no_tribe")
+                        .action(ArgAction::SetTrue)
+                        .help("Do not show tribe images"),
+                    Arg::new("no_type")
+                        .long("no_type")
+                        .action(ArgAction::SetTrue)
+                        .help("Do not show type images"),
                 ]).arg_required_else_help(true),
         )
         .subcommand(
@@ -387,6 +954,39 @@ fn cli() -> Command {
                         .value_name("PREFIX_PATH")
                         .value_parser(clap::value_parser!(PathBuf))
                         .help("Prefix for generated images"),
+                    remap_arg(),
+                    atlas_arg(),
+                ]).arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("psfb-import")
+                .about("Create psfb file from RGBA images, sprites are stored in order of arguments")
+                .args([
+                    Arg::new("palette")
+                        .long("palette")
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .value_name("PALETTE_PATH")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Path to palette file"),
+                    Arg::new("output")
+                        .long("output")
+                        .required(true)
+                        .action(ArgAction::Set)
+                        .value_name("FILE_PATH")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Path to output PSFB file"),
+                    Arg::new("dither")
+                        .long("dither")
+                        .action(ArgAction::SetTrue)
+                        .help("Dither colours which are not in palette"),
+                    Arg::new("images")
+                        .required(true)
+                        .num_args(1..)
+                        .action(ArgAction::Append)
+                        .value_name("IMAGE")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .help("Sprite images"),
                 ]).arg_required_else_help(true),
         )
 }
@@ -394,7 +994,7 @@ fn cli() -> Command {
 enum TextureType {
     Land,
     Globe,
-    Minimap,
+    Minimap(bool),
 }
 
 fn write_img_stdout<P, C>(img: &ImageBuffer<P, C>, format: ImageOutputFormat)
@@ -414,15 +1014,18 @@ fn write_dyn_img_stdout(img: &DynamicImage, format: ImageOutputFormat) {
 
 fn make_texture_land(tex_type: TextureType
                      , level_num: u8
-                     , base: &Path
+                     , fs: &dyn ResourceFs
                      , level_type_opt: Option<&String>
+                     , sunlight: Option<&Sunlight>
                      , _tex_move: Option<(u32, u32)>
-                     ) {
-    let level_res = LevelRes::new(base, level_num, level_type_opt.map(|s| s.as_str()));
+                     ) -> Result<()> {
+    let level_res = LevelRes::new(fs, level_num, level_type_opt.map(|s| s.as_str()))?;
 
     let land_size = level_res.landscape.land_size();
     let params_globe = &level_res.params;
-    let land = LandPos::from_landscape_sun(&level_res.landscape);
+    let sunlight = sunlight.unwrap_or(&level_res.sunlight);
+    let mut land = LandPos::from_landscape_sun(&level_res.landscape, sunlight);
+    LandPos::set_flags(&mut land, &level_res.land_flags);
     let landscape = LandscapeFull::new(land_size, land);
 
     //let (h, v) = tex_move.unwrap_or((0, 0));
@@ -434,13 +1037,14 @@ fn make_texture_land(tex_type: TextureType
         TextureType::Globe => {
             texture_globe(land_size, &landscape, params_globe)
         }
-        TextureType::Minimap => {
-            texture_minimap(land_size, true, &landscape, &params_globe.bigf0)
+        TextureType::Minimap(show_all) => {
+            texture_minimap(land_size, show_all, &landscape, &params_globe.bigf0)
         }
     };
     let img = draw_image_pal(&params_globe.palette, img);
 
     write_img_stdout(&img, DEFAULT_IMG_FORMAT);
+    Ok(())
 }
 
 fn parse_move(s: &str) -> Option<(u32, u32)> {
@@ -453,33 +1057,93 @@ fn parse_move(s: &str) -> Option<(u32, u32)> {
     Some((a.parse().unwrap(), b.parse().unwrap()))
 }
 
+fn parse_sunlight(s: &str) -> std::result::Result<Sunlight, String> {
+    let parts = s.split(',')
+                 .map(|v| v.trim().parse::<u8>().map_err(|e| format!("{v}: {e}")))
+                 .collect::<std::result::Result<Vec<u8>, String>>()?;
+    match parts[..] {
+        [v1, v2, v3] => Ok(Sunlight::new(v1, v2, v3)),
+        _ => Err("expected 3 comma separated values".to_string()),
+    }
+}
+
 fn parse_ids(s: &str) -> HashSet<usize> {
     HashSet::from_iter(s.split(',').map(|s| s.parse::<usize>().unwrap()))
 }
 
+fn image_error(e: image::ImageError, path: &Path) -> Error {
+    Error::from(std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())).in_file(path)
+}
+
+// Format is selected by extension: .pal (JASC), .gpl, .png (swatch), other are pop3 palettes
+fn load_palette(fs: &dyn ResourceFs, path: &Path) -> Result<Palette> {
+    let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let data = read_resource_or_file(fs, path)?;
+    match ext.as_str() {
+        "pal" | "gpl" => {
+            let text = String::from_utf8_lossy(&data);
+            let pal = if ext == "pal" { Palette::from_jasc(&text) } else { Palette::from_gpl(&text) };
+            pal.map_err(|e| e.in_file(path))
+        },
+        "png" => {
+            let img = image::load_from_memory(&data).map_err(|e| image_error(e, path))?.to_rgba8();
+            Palette::from_swatch_rgba(img.width() as usize, img.height() as usize, img.as_raw())
+                .map_err(|e| e.in_file(path))
+        },
+        _ => Palette::from_bytes(&data).map_err(|e| e.in_file(path)),
+    }
+}
+
+fn read_palette(fs: &dyn ResourceFs, path: Option<PathBuf>) -> Result<Option<PaletteArray>> {
+    match path {
+        Some(path) => Ok(Some(pal_array(&load_palette(fs, &path)?))),
+        None => Ok(None),
+    }
+}
+
+fn read_psfb(fs: &dyn ResourceFs, path: &Path) -> Result<ContainerPSFB> {
+    let data = read_resource_or_file(fs, path)?;
+    ContainerPSFB::from_reader_one(&mut data.as_slice()).map_err(|e| e.in_file(path))
+}
+
+fn read_sprite_image(path: &Path, pal: &Palette, dither: bool) -> Result<Image> {
+    let img = image::open(path).map_err(|e| image_error(e, path))?;
+    let img = img.to_rgba8();
+    Ok(quantize_rgba(img.width() as usize, img.height() as usize, img.as_raw(), pal, dither))
+}
+
 fn main() {
-    let base_path = Path::new(DEFAULT_BASE_PATH);
+    if let Err(e) = run() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
 
+fn run() -> Result<()> {
     let matches = cli().get_matches();
+    let base_path = matches.get_one::<PathBuf>("base").cloned().unwrap_or_else(|| PathBuf::from(DEFAULT_BASE_PATH));
+    let fs = open_resource_fs(&base_path)?;
     match matches.subcommand() {
         Some(("globe", sub_matches)) => {
             let level_num = sub_matches.get_one::<String>("num").expect("required").parse().unwrap();
             let level_type = sub_matches.get_one::<String>("landtype");
             let tex_move = sub_matches.get_one::<String>("move").and_then(|s| parse_move(s));
-            make_texture_land(TextureType::Globe, level_num, base_path, level_type, tex_move);
+            let sunlight = sub_matches.get_one::<Sunlight>("sunlight");
+            make_texture_land(TextureType::Globe, level_num, &fs, level_type, sunlight, tex_move)?;
         }
         Some(("land", sub_matches)) => {
             let level_num = sub_matches.get_one::<String>("num").expect("required").parse().unwrap();
             let level_type = sub_matches.get_one::<String>("landtype");
             let tex_move = sub_matches.get_one::<String>("move").and_then(|s| parse_move(s));
-            make_texture_land(TextureType::Land, level_num, base_path, level_type, tex_move);
+            let sunlight = sub_matches.get_one::<Sunlight>("sunlight");
+            make_texture_land(TextureType::Land, level_num, &fs, level_type, sunlight, tex_move)?;
         }
         Some(("bl320", sub_matches)) => {
             let level_type: String = sub_matches.get_one::<String>("landtype").expect("required").parse().unwrap();
-            let paths = LevelPaths::from_default_dir(base_path, &level_type);
-            let pal = read_pal(&paths);
+            let paths = LevelPaths::from_default_dir(&level_type);
+            let pal = read_pal(&fs, &paths)?;
             let allocator = image_allocator_1d_horizontal();
-            let provider = read_bl320(&allocator, &paths.bl320);
+            let provider = read_bl320(&fs, &allocator, &paths.bl320)?;
             let img = draw_image_pal(&pal, provider.get_image());
             write_img_stdout(&img, DEFAULT_IMG_FORMAT);
         }
@@ -487,22 +1151,24 @@ fn main() {
             let level_type: String = sub_matches.get_one::<String>("landtype").expect("required").parse().unwrap();
             let width: usize = sub_matches.get_one::<String>("width").expect("required").parse().unwrap();
             let height: usize = sub_matches.get_one::<String>("height").expect("required").parse().unwrap();
-            let paths = LevelPaths::from_default_dir(base_path, &level_type);
-            let pal = read_pal(&paths);
+            let paths = LevelPaths::from_default_dir(&level_type);
+            let pal = read_pal(&fs, &paths)?;
             let allocator = image_allocator_1d_vertical();
-            let provider = read_bl160(width, height, &allocator, &paths.bl160);
+            let provider = read_bl160(&fs, width, height, &allocator, &paths.bl160)?;
             let img = draw_image_pal(&pal, provider.get_image());
             write_img_stdout(&img, DEFAULT_IMG_FORMAT);
         }
         Some(("minimap", sub_matches)) => {
             let level_num = sub_matches.get_one::<String>("num").expect("required").parse().unwrap();
             let level_type = sub_matches.get_one::<String>("landtype");
-            make_texture_land(TextureType::Minimap, level_num, base_path, level_type, None);
+            let show_all = !sub_matches.get_flag("fog");
+            let sunlight = sub_matches.get_one::<Sunlight>("sunlight");
+            make_texture_land(TextureType::Minimap(show_all), level_num, &fs, level_type, sunlight, None)?;
         }
         Some(("water", sub_matches)) => {
             let level_num = sub_matches.get_one::<String>("num").expect("required").parse().unwrap();
             let offset = sub_matches.get_one::<String>("offset").expect("required").parse().unwrap();
-            let level_res = LevelRes::new(base_path, level_num, None);
+            let level_res = LevelRes::new(&fs, level_num, None)?;
             let img = texture_water(offset, &level_res.params);
             let img = draw_image_pal(&level_res.params.palette, img);
             write_img_stdout(&img, DEFAULT_IMG_FORMAT);
@@ -510,76 +1176,133 @@ fn main() {
         Some(("bigf0", sub_matches)) => {
             let level_num = sub_matches.get_one::<String>("num").expect("required").parse().unwrap();
             let height = sub_matches.get_one::<String>("height").expect("required").parse().unwrap();
-            let level_res = LevelRes::new(base_path, level_num, None);
+            let level_res = LevelRes::new(&fs, level_num, None)?;
             let img = texture_bigf0(height, &level_res.params);
             let img = draw_image_pal(&level_res.params.palette, img);
             write_img_stdout(&img, DEFAULT_IMG_FORMAT);
         }
         Some(("disp", sub_matches)) => {
             let level_num = sub_matches.get_one::<String>("num").expect("required").parse().unwrap();
-            let level_res = LevelRes::new(base_path, level_num, None);
+            let level_res = LevelRes::new(&fs, level_num, None)?;
             let img = make_disp_texture2(&level_res.params);
             write_img_stdout(&img, DEFAULT_IMG_FORMAT);
         }
         Some(("palette", sub_matches)) => {
-            let level_num = sub_matches.get_one::<String>("num").expect("required").parse().unwrap();
-            let level_res = LevelRes::new(base_path, level_num, None);
-            let img = draw_palette(&level_res.params.palette, 1024, 1024, 128);
-            write_img_stdout(&img, DEFAULT_IMG_FORMAT);
+            let input: Option<PathBuf> = sub_matches.get_one("input").cloned();
+            let level_num: Option<u8> = sub_matches.get_one::<String>("num").map(|s| s.parse().unwrap());
+            let pal = match (input, level_num) {
+                (Some(path), _) => load_palette(&fs, &path)?,
+                _ if sub_matches.get_flag("fenew") => Palette::from_fenew(&fs)?,
+                (None, Some(level_num)) => LevelRes::new(&fs, level_num, None)?.params.palette,
+                (None, None) => {
+                    return Err(Error::BadFormat("level number, --fenew or --input is required".to_string()));
+                },
+            };
+            match sub_matches.get_one::<String>("format").map(|s| s.as_str()) {
+                Some("dat") => std::io::stdout().write_all(pal.as_bytes())?,
+                Some("jasc") => print!("{}", pal.to_jasc()),
+                Some("gpl") => print!("{}", pal.to_gpl("pop3")),
+                Some("png") => {
+                    let img = draw_image_pal(&pal, Palette::swatch(16));
+                    write_img_stdout(&img, ImageOutputFormat::Png);
+                },
+                _ => {
+                    let img = draw_palette(&pal, 1024, 1024, 128);
+                    write_img_stdout(&img, DEFAULT_IMG_FORMAT);
+                },
+            }
+        }
+        Some(("objects", sub_matches)) if sub_matches.subcommand_matches("export").is_some() => {
+            let export_matches = sub_matches.subcommand_matches("export").unwrap();
+            export_objects(&fs, export_matches)?;
+        }
+        Some(("objects", sub_matches)) if sub_matches.subcommand_matches("footprints").is_some() => {
+            let footprints_matches = sub_matches.subcommand_matches("footprints").unwrap();
+            draw_footprints(&fs, footprints_matches)?;
         }
         Some(("objects", sub_matches)) => {
             let bank_num = sub_matches.get_one::<String>("num").expect("required");
-            let paths = ObjectPaths::from_default_dir(base_path, bank_num);
-            let objects = ObjectRaw::from_file_vec(&paths.objs0_dat);
-            let points = PointRaw::from_file_vec(&paths.pnts0);
-            let faces = FaceRaw::from_file_vec(&paths.facs0);
-            let shapes = Shape::from_file_vec(&paths.shapes);
+            let paths = ObjectPaths::from_default_dir(bank_num);
+            let objects = ObjectRaw::from_fs_vec(&fs, &paths.objs0_dat)?;
+            let points = PointRaw::from_fs_vec(&fs, &paths.pnts0)?;
+            let faces = FaceRaw::from_fs_vec(&fs, &paths.facs0)?;
+            let shapes = Shape::from_fs_vec(&fs, &paths.shapes)?;
+            let offsets = sub_matches.get_flag("offsets");
             println!("Num objects = {}", objects.len());
-            for obj in &objects {
-                println!("  {:?}", obj);
-            }
+            print_records(&objects, 0, offsets);
             println!("Num shapes = {}", shapes.len());
-            for shape in shapes {
-                println!("  {:?}", shape);
-            }
+            print_records(&shapes, 0, offsets);
             println!("Num points = {}", points.len());
-            for point in &points {
-                println!("  {:?}", point);
-            }
+            print_records(&points, 0, offsets);
             println!("Num faces = {}", faces.len());
-            for face in &faces {
-                println!("  {:?}", face);
+            print_records(&faces, 0, offsets);
+        }
+        Some(("levels", _)) => {
+            let catalog = LevelCatalog::scan(&fs)?;
+            println!("Num levels = {}", catalog.levels.len());
+            for level in &catalog.levels {
+                println!("{:3}: {:?} landscape={} size={}", level.num, level.name, level.landscape_type, level.dat_size);
             }
         }
+        Some(("header", sub_matches)) => {
+            let level_num = sub_matches.get_one::<String>("num").expect("required").parse().unwrap();
+            let hdr_path = LevelPaths::hdr_path(Path::new("levels"), level_num);
+            let header = LevelHeader::from_fs(&fs, &hdr_path)?;
+            print_header(&header);
+        }
         Some(("units", sub_matches)) => {
             let level_num = sub_matches.get_one::<String>("num").expect("required").parse().unwrap();
-            let level_res = LevelRes::new(base_path, level_num, None);
+            let level_res = LevelRes::new(&fs, level_num, None)?;
+            let offsets = sub_matches.get_flag("offsets");
             println!("Num units = {}", level_res.units.len());
-            for unit in &level_res.units {
+            for (index, unit) in level_res.units.iter().enumerate() {
                 if unit.unit_class != 0 {
-                    println!("  {:?}", unit);
+                    if offsets {
+                        print_record(LEVEL_UNITS_OFFSET, index, unit);
+                    } else {
+                        let unit = Unit::from_raw(unit);
+                        println!("  {:?}: {:?} {} tribe={:?} cell=({:?}, {:?}) angle={:?}"
+                                 , index, unit.class, unit.subtype, unit.tribe
+                                 , unit.cell_x, unit.cell_y, unit.angle);
+                    }
                 }
             }
-            for tribe in &level_res.tribes {
-                println!("  {:?}", tribe);
+            if offsets {
+                print_records(&level_res.tribes, LEVEL_TRIBES_OFFSET, offsets);
+            } else {
+                for tribe in level_res.tribe_configs() {
+                    println!("  Tribe {:?}: computer={:?}, allies=0x{:02x}, buildings=0x{:08x}, unknown={:?}"
+                             , tribe.tribe, tribe.is_computer, tribe.allies, tribe.buildings, tribe.unknown);
+                    println!("    spells = {:?}", tribe.spell_names());
+                }
             }
             println!("  {:?}", level_res.sunlight);
         }
         Some(("anims", sub_matches)) => {
             let psfb_path = sub_matches.get_one::<PathBuf>("psfb_path");
             println!("PSFB = {:?}", psfb_path);
-            let psfb_container = psfb_path.and_then(|p| ContainerPSFB::from_file(Path::new(&p)));
-            let anims_data = AnimationsData::from_path(&base_path.join("data"));
+            let psfb_container = match psfb_path {
+                Some(p) => Some(read_psfb(&fs, p)?),
+                None => None,
+            };
+            let anims_data = AnimationsData::from_fs(&fs, Path::new("data"))?;
+            let offsets = sub_matches.get_flag("offsets");
             println!("Num vele={:?}, vfra={:?}, vstart={:?}"
                     , anims_data.vele.len(), anims_data.vfra.len(), anims_data.vstart.len());
-            for (index, vele) in (0..).zip(&anims_data.vele) {
-                println!("  {:?}:{:?}", index, vele);
-            }
-            for (index, vfra) in (0..).zip(&anims_data.vfra) {
-                println!("  {:?}:{:?}", index, vfra);
-            }
-            for vstart in &anims_data.vstart {
-                println!("  {:?}", vstart);
+            if offsets {
+                print_records(&anims_data.vele, 0, offsets);
+                print_records(&anims_data.vfra, 0, offsets);
+                print_records(&anims_data.vstart, 0, offsets);
+            } else {
+                for (index, vele) in (0..).zip(&anims_data.vele) {
+                    println!("  {:?}:{:?}", index, vele);
+                }
+                for (index, vfra) in (0..).zip(&anims_data.vfra) {
+                    println!("  {:?}:{:?}", index, vfra);
+                }
+                for vstart in &anims_data.vstart {
+                    println!("  {:?}", vstart);
+                }
             }
             let anim_seq_vec = AnimationSequence::from_data(&anims_data);
             for anim_seq in &anim_seq_vec {
@@ -605,7 +1328,7 @@ fn main() {
             }
         }
         Some(("anims_draw", sub_matches)) => {
-            let anims_data = AnimationsData::from_path(&base_path.join("data"));
+            let anims_data = AnimationsData::from_fs(&fs, Path::new("data"))?;
             let anim_seq_vec = AnimationSequence::from_data(&anims_data);
             let file_path: PathBuf = sub_matches.get_one("path").cloned().unwrap();
             let palette_path: Option<PathBuf> = sub_matches.get_one("palette").cloned();
@@ -613,98 +1336,154 @@ fn main() {
             let composer_type = {
                 sub_matches.get_one::<String>("composer").unwrap_or(&s)
             };
-            let palette = if let Some(path) = palette_path {
-                let mut file = File::options().read(true).open(path).unwrap();
-                let mut pal = Vec::new();
-                file.read_to_end(&mut pal).unwrap();
-                let pal_tup = seq_to_pal(&pal);
-                Some(pal_tup)
-            } else {
-                None
-            };
+            let palette = read_palette(&fs, palette_path)?;
             let frames_ids = sub_matches.get_one::<String>("ids").map(|s| parse_ids(s)).unwrap_or_default();
             let img_size = 800;
             let with_tribe: bool = !sub_matches.get_flag("no_tribe");
             let with_type: bool = !sub_matches.get_flag("no_type");
-            let anims_config = AnimationsConfig{img_size, with_tribe, with_type};
-            if let Some(c) = ContainerPSFB::from_file(&file_path) {
-                let img = {
-                    match composer_type.as_str() {
-                        "ul" => {
-                            let composer = ULCentreComposer{vertical: 5, horizontal: 5};
-                            draw_anim_frames(&anim_seq_vec, &c, &palette, &frames_ids, &composer, &anims_config)
-                        },
-                        _ => {
-                            let composer = URCentreComposer{vertical: 5, horizontal: 5};
-                            draw_anim_frames(&anim_seq_vec, &c, &palette, &frames_ids, &composer, &anims_config)
-                        },
-                    }
+            let fade_level: Option<usize> = sub_matches.get_one("fade").copied();
+            let params = match sub_matches.get_flag("ghost") || fade_level.is_some() {
+                true => {
+                    let landtype = sub_matches.get_one::<String>("landtype").expect("default");
+                    Some(GlobeTextureParams::from_level(&fs, &LevelPaths::from_default_dir(landtype))?)
+                },
+                false => None,
+            };
+            let fade_table = params.as_ref().map(|p| p.fade_table()).transpose()?;
+            let blend_table = params.as_ref().map(|p| p.blend_table()).transpose()?;
+            let layer_op = match (fade_level, &fade_table, &blend_table) {
+                (Some(level), Some(table), _) => Some(PixelOp::Fade(table, level)),
+                (None, _, Some(table)) => Some(PixelOp::Blend(table)),
+                _ => None,
+            };
+            let anims_config = AnimationsConfig{img_size, with_tribe, with_type, remap: read_remap(&fs, sub_matches)?, layer_op};
+            let c = read_psfb(&fs, &file_path)?;
+            let atlas_path: Option<PathBuf> = sub_matches.get_one("atlas").cloned();
+            let (img, entries) = {
+                match composer_type.as_str() {
+                    "ul" => {
+                        let composer = ULCentreComposer{vertical: 5, horizontal: 5};
+                        draw_anim_frames(&anim_seq_vec, &c, &palette, &frames_ids, &composer, &anims_config)?
+                    },
+                    _ => {
+                        let composer = URCentreComposer{vertical: 5, horizontal: 5};
+                        draw_anim_frames(&anim_seq_vec, &c, &palette, &frames_ids, &composer, &anims_config)?
+                    },
+                }
+            };
+            match atlas_path {
+                Some(path) => write_atlas(&path, &img, &entries)?,
+                None => write_dyn_img_stdout(&img, DEFAULT_IMG_FORMAT),
+            }
+        }
+        Some(("anims_export", sub_matches)) => {
+            let anims_data = AnimationsData::from_fs(&fs, Path::new("data"))?;
+            let anim_seq_vec = AnimationSequence::from_data(&anims_data);
+            let file_path: PathBuf = sub_matches.get_one("path").cloned().unwrap();
+            let output: PathBuf = sub_matches.get_one("output").cloned().unwrap();
+            let palette_path: Option<PathBuf> = sub_matches.get_one("palette").cloned();
+            let palette = read_palette(&fs, palette_path)?;
+            let seq_ids = sub_matches.get_one::<String>("seq").map(|s| parse_ids(s)).unwrap_or_default();
+            let format = sub_matches.get_one::<String>("format").unwrap();
+            let delay: u32 = *sub_matches.get_one("delay").unwrap();
+            let with_tribe: bool = !sub_matches.get_flag("no_tribe");
+            let with_type: bool = !sub_matches.get_flag("no_type");
+            let anims_config = AnimationsConfig{img_size: 0, with_tribe, with_type, remap: RemapTable::identity(), layer_op: None};
+            let ul_composer = ULCentreComposer{vertical: 5, horizontal: 5};
+            let ur_composer = URCentreComposer{vertical: 5, horizontal: 5};
+            let is_ul = sub_matches.get_one::<String>("composer").map(String::as_str).unwrap_or("ul") == "ul";
+            let c = read_psfb(&fs, &file_path)?;
+            std::fs::create_dir_all(&output).map_err(|e| Error::from(e).in_file(&output))?;
+            for anim_seq in &anim_seq_vec {
+                if anim_seq.frames.is_empty() || (!seq_ids.is_empty() && !seq_ids.contains(&anim_seq.index)) {
+                    continue;
+                }
+                let frames = if is_ul {
+                    draw_anim_sequence(anim_seq, &c, &ul_composer, &anims_config)?
+                } else {
+                    draw_anim_sequence(anim_seq, &c, &ur_composer, &anims_config)?
                 };
-                write_dyn_img_stdout(&img, DEFAULT_IMG_FORMAT);
+                let path = output.join(format!("seq_{:04}.{}", anim_seq.index, if format == "apng" { "png" } else { "gif" }));
+                if format == "apng" {
+                    write_anim_apng(&path, frames, &palette, delay)?;
+                } else {
+                    write_anim_gif(&path, frames, &palette, delay)?;
+                }
+                println!("{}", path.display());
             }
         }
         Some(("pls", sub_matches)) => {
             let path = sub_matches.get_one::<PathBuf>("pls_path").expect("required");
-            let pls_data = decode_pls(path);
+            let pls_data = decode_pls(&fs, path)?;
             std::io::stdout().write_all(&pls_data).unwrap();
         }
+        Some(("psfb-import", sub_matches)) => {
+            let palette_path: PathBuf = sub_matches.get_one("palette").cloned().unwrap();
+            let output: PathBuf = sub_matches.get_one("output").cloned().unwrap();
+            let dither = sub_matches.get_flag("dither");
+            let pal = load_palette(&fs, &palette_path)?;
+            let images = sub_matches.get_many::<PathBuf>("images").unwrap()
+                .map(|path| read_sprite_image(path, &pal, dither))
+                .collect::<Result<Vec<Image>>>()?;
+            let c = ContainerPSFB::from_images(&images)?;
+            c.to_file(&output)?;
+            println!("Written {} sprites to {:?}", c.len(), output);
+        }
         Some(("psfb", sub_matches)) => {
             let file_path: PathBuf = sub_matches.get_one("path").cloned().unwrap();
             let palette_path: Option<PathBuf> = sub_matches.get_one("palette").cloned();
             let info: bool = sub_matches.get_flag("info");
             let start_num: Option<u32> = sub_matches.get_one("start").copied();
             let num: Option<u32> = sub_matches.get_one("num").copied();
-            let palette = if let Some(path) = palette_path {
-                let mut file = File::options().read(true).open(path).unwrap();
-                let mut pal = Vec::new();
-                file.read_to_end(&mut pal).unwrap();
-                let pal_tup = seq_to_pal(&pal);
-                Some(pal_tup)
+            let palette = read_palette(&fs, palette_path)?;
+            let remap = read_remap(&fs, sub_matches)?;
+            let op = PixelOp::Remap(&remap);
+            let c = read_psfb(&fs, &file_path)?;
+            if info {
+                println!("PSFB file '{file_path:?}': ");
+                println!("    size = {:?}", c.size());
+                println!("    sprites count = {:?}", c.len());
+                for sprite in c.sprites_info() {
+                    let size = sprite.width as usize * sprite.height as usize;
+                    println!(" Sprite index={:?} offset={:?}/0x{:x}, size={:?}, width={:?}, height={:?}"
+                             , sprite.index, sprite.offset, sprite.offset, size, sprite.width, sprite.height);
+                }
             } else {
-                None
-            };
-            if let Some(c) = ContainerPSFB::from_file(&file_path) {
-                if info {
-                    println!("PSFB file '{file_path:?}': ");
-                    println!("    size = {:?}", c.size());
-                    println!("    sprites count = {:?}", c.len());
-                    for sprite in c.sprites_info() {
-                        let size = sprite.width as usize * sprite.height as usize;
-                        println!(" Sprite index={:?} offset={:?}/0x{:x}, size={:?}, width={:?}, height={:?}"
-                                 , sprite.index, sprite.offset, sprite.offset, size, sprite.width, sprite.height);
+                let (start, num) = match (start_num, num) {
+                    (Some(i), Some(n)) => {
+                        (i as usize, n as usize)
+                    }
+                    (None, Some(n)) => {
+                        (0, n as usize)
+                    }
+                    (Some(i), None) => {
+                        (i as usize, 0)
+                    }
+                    (None, None) => {
+                        (0, c.len())
+                    }
+                };
+                let atlas_path: Option<PathBuf> = sub_matches.get_one("atlas").cloned();
+                if let Some(path) = atlas_path {
+                    let (img, entries) = draw_sprites_img(&c, start, num.max(1), &palette, op)?;
+                    write_atlas(&path, &img, &entries)?;
+                } else if num <= 1 {
+                    if let Some(image) = c.get_image_op(start, op)? {
+                        let img = draw_image(&palette, image);
+                        write_dyn_img_stdout(&img, DEFAULT_IMG_FORMAT);
                     }
                 } else {
-                    let (start, num) = match (start_num, num) {
-                        (Some(i), Some(n)) => {
-                            (i as usize, n as usize)
-                        }
-                        (None, Some(n)) => {
-                            (0, n as usize)
-                        }
-                        (Some(i), None) => {
-                            (i as usize, 0)
-                        }
-                        (None, None) => {
-                            (0, c.len())
-                        }
-                    };
-                    if num <= 1 {
-                        if let Some(image) = c.get_image(start) {
-                            let img = draw_image(&palette, image);
-                            write_dyn_img_stdout(&img, DEFAULT_IMG_FORMAT);
-                        }
+                    let prefix_opt: Option<PathBuf> = sub_matches.get_one("prefix").cloned();
+                    if let Some(prefix) = prefix_opt {
+                        draw_sprites(&c, start, num, &prefix, &palette, op)?;
                     } else {
-                        let prefix_opt: Option<PathBuf> = sub_matches.get_one("prefix").cloned();
-                        if let Some(prefix) = prefix_opt {
-                            draw_sprites(&c, start, num, &prefix, &palette);
-                        } else {
-                            let img = draw_sprites_img(&c, start, num, &palette);
-                            write_dyn_img_stdout(&img, DEFAULT_IMG_FORMAT);
-                        }
+                        let (img, _) = draw_sprites_img(&c, start, num, &palette, op)?;
+                        write_dyn_img_stdout(&img, DEFAULT_IMG_FORMAT);
                     }
                 }
             }
         }
         _ => {}
     }
+    Ok(())
 }
@@ -22,7 +22,8 @@ use faithful::view::*;
 use faithful::intersect::intersect_iter;
 
 use faithful::landscape::{LandscapeMesh, LandscapeModel};
-use faithful::pop::level:: LevelRes;
+use faithful::pop::level::{LevelCatalog, LevelRes};
+use faithful::pop::fs::{ResourceFs, open_resource_fs};
 use faithful::pop::landscape::{make_texture_land, draw_texture_u8};
 
 use faithful::opengl::gl::{GlCtx, new_gl_ctx};
@@ -184,7 +185,7 @@ impl LevelAssets {
             let params = TextureParams{target: GL_TEXTURE_1D, internal_format: GL_RGBA8UI, format: GL_RGBA_INTEGER, data_type: GL_UNSIGNED_BYTE, nearest: true};
             let uniform = Some(1);
             let width = level_res.landscape.land_size();
-            GlTexture::new_1d(gl, uniform, &params, width, level_res.params.palette.as_slice())
+            GlTexture::new_1d(gl, uniform, &params, width, level_res.params.palette.as_bytes())
         }.unwrap();
 
         let tex_disp = {
@@ -209,7 +210,7 @@ impl LevelAssets {
     }
 
     pub fn update(&mut self, level_res: &LevelRes) {
-        self.tex_palette.set_data(level_res.params.palette.as_slice());
+        self.tex_palette.set_data(level_res.params.palette.as_bytes());
         self.tex_disp.set_data(level_res.params.disp0.as_slice());
         self.tex_bigf.set_data(level_res.params.bigf0.as_slice());
     }
@@ -355,7 +356,7 @@ impl CpuLandscapeProgram {
             let params = TextureParams{target: GL_TEXTURE_1D, internal_format: GL_RGBA8UI, format: GL_RGBA_INTEGER, data_type: GL_UNSIGNED_BYTE, nearest: true};
             let uniform = Some(1);
             let width = level_res.landscape.land_size();
-            GlTexture::new_1d(gl, uniform, &params, width, level_res.params.palette.as_slice())
+            GlTexture::new_1d(gl, uniform, &params, width, level_res.params.palette.as_bytes())
         }.unwrap();
 
         program.set_uniform(0, uniforms.mvp.clone());
@@ -371,7 +372,7 @@ impl CpuLandscapeProgram {
     }
 
     fn new_rc_ref(gl: &GlCtx, level_res: &LevelRes, uniforms: &LevelUniforms) -> Rc<RefCell<dyn LandscapeProgram>> {
-        let land_texture = make_texture_land(level_res, None);
+        let land_texture = make_texture_land(level_res, None, None);
         Rc::new(RefCell::new(Self::new(gl, level_res, &land_texture, uniforms)))
     }
 }
@@ -382,9 +383,9 @@ impl LandscapeProgram for CpuLandscapeProgram {
     }
 
     fn update(&mut self, level_res: &LevelRes) {
-        let land_texture = make_texture_land(level_res, None);
+        let land_texture = make_texture_land(level_res, None, None);
         self.texture.set_data(&land_texture);
-        self.tex_palette.set_data(level_res.params.palette.as_slice());
+        self.tex_palette.set_data(level_res.params.palette.as_bytes());
     }
 }
 
@@ -428,7 +429,7 @@ impl CpuFullLandscapeProgram {
     }
 
     fn new_rc_ref(gl: &GlCtx, level_res: &LevelRes, uniforms: &LevelUniforms) -> Rc<RefCell<dyn LandscapeProgram>> {
-        let land_texture = make_texture_land(level_res, None);
+        let land_texture = make_texture_land(level_res, None, None);
         Rc::new(RefCell::new(Self::new(gl, level_res, &land_texture, uniforms)))
     }
 }
@@ -439,7 +440,7 @@ impl LandscapeProgram for CpuFullLandscapeProgram {
     }
 
     fn update(&mut self, level_res: &LevelRes) {
-        let land_texture = make_texture_land(level_res, None);
+        let land_texture = make_texture_land(level_res, None, None);
         let size = level_res.landscape.land_size() * 32;
         let texture = draw_texture_u8(&level_res.params.palette, size, &land_texture);
         self.texture.set_data(&texture);
@@ -504,10 +505,10 @@ fn make_landscape_mode(gl: &GlCtx, uniforms: &LevelUniforms, landscape_mesh: &La
     model_main
 }
 
-fn update_level(base: &Path, level_num: u8, landscape_mesh: &mut LandscapeMeshS, program_container: &mut LandscapeProgramContainer, heights_buffer: &mut GlShaderStorage) -> RefCell<LevelRes> {
+fn update_level(fs: &dyn ResourceFs, level_num: u8, landscape_mesh: &mut LandscapeMeshS, program_container: &mut LandscapeProgramContainer, heights_buffer: &mut GlShaderStorage) -> faithful::pop::Result<RefCell<LevelRes>> {
     let level_res = {
         let level_type = None;
-        LevelRes::new(base, level_num, level_type)
+        LevelRes::new(fs, level_num, level_type)?
     };
     landscape_mesh.set_heights(&level_res.landscape.height);
     heights_buffer.update(0, {
@@ -515,7 +516,7 @@ fn update_level(base: &Path, level_num: u8, landscape_mesh: &mut LandscapeMeshS,
         &landscape.to_vec()
     }).unwrap();
     program_container.update_programs(&level_res);
-    RefCell::new(level_res)
+    Ok(RefCell::new(level_res))
 }
 
 fn render(gl: &GlCtx, program_landscape: &GlProgram, program_select: &GlProgram, scene: &Scene) {
@@ -558,7 +559,7 @@ fn cli() -> Command {
             .action(ArgAction::Set)
             .value_name("BASE_PATH")
             .value_parser(clap::value_parser!(PathBuf))
-            .help("Path to POP3 directory"),
+            .help("Path to POP3 directory or zip archive"),
         Arg::new("level")
             .long("level")
             .action(ArgAction::Set)
@@ -633,10 +634,32 @@ fn main() {
     log::debug!("Pixel format of the window's GL context: {:?}", windowed_context.get_pixel_format());
     let mut level_num = app_config.level.unwrap_or(1);
     let base = app_config.base.unwrap_or_else(|| Path::new("/opt/sandbox/pop").to_path_buf());
+    let fs = match open_resource_fs(&base) {
+        Ok(fs) => fs,
+        Err(e) => {
+            log::error!("Cannot open {base:?}: {e}");
+            return;
+        }
+    };
+
+    let catalog = match LevelCatalog::scan(&fs) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            log::error!("Cannot list levels: {e}");
+            return;
+        }
+    };
+    log::debug!("Found {} levels", catalog.levels.len());
 
     let level_res = {
         let level_type = app_config.landtype.as_deref();
-        RefCell::new(LevelRes::new(&base, level_num, level_type))
+        match LevelRes::new(&fs, level_num, level_type) {
+            Ok(level_res) => RefCell::new(level_res),
+            Err(e) => {
+                log::error!("Cannot load level {level_num:?}: {e}");
+                return;
+            }
+        }
     };
 
     let mut landscape_mesh: LandscapeMeshS = {
@@ -797,16 +820,17 @@ fn main() {
                         do_render = true;
                     },
                     KI { state: ElementState::Pressed, virtual_keycode: Some(VKC::B), .. } => {
-                        level_num = (level_num + 1) % 26;
-                        if level_num == 0 {
-                            level_num = 1;
+                        level_num = catalog.next(level_num).unwrap_or(level_num);
+                        if let Err(e) = update_level(&fs, level_num, &mut landscape_mesh, &mut program_container, &mut heights_buffer) {
+                            log::error!("Cannot load level {level_num:?}: {e}");
                         }
-                        update_level(&base, level_num, &mut landscape_mesh, &mut program_container, &mut heights_buffer);
                         do_render = true;
                     },
                     KI { state: ElementState::Pressed, virtual_keycode: Some(VKC::V), .. } => {
-                        level_num = if level_num == 1 { 25 } else { level_num - 1 };
-                        update_level(&base, level_num, &mut landscape_mesh, &mut program_container, &mut heights_buffer);
+                        level_num = catalog.prev(level_num).unwrap_or(level_num);
+                        if let Err(e) = update_level(&fs, level_num, &mut landscape_mesh, &mut program_container, &mut heights_buffer) {
+                            log::error!("Cannot load level {level_num:?}: {e}");
+                        }
                         do_render = true;
                     },
                     KI { state: ElementState::Pressed, virtual_keycode: Some(VKC::L), .. } => {
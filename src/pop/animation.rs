@@ -1,60 +1,45 @@
 use std::path::Path;
-use std::fs::File;
 use std::io::Read;
-use core::mem::size_of;
 
-use crate::pop::types::{BinDeserializer, from_reader, ImageInfo};
+use crate::pop::types::{BinDeserializer, ImageInfo, ImagePos, ImageFlip, pop_record};
+use crate::pop::error::Result;
+use crate::pop::fs::ResourceFs;
 
 /******************************************************************************/
 
-#[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
-pub struct VeleRaw {
-    pub sprite_index: u16,
-    pub coord_x: i16,
-    pub coord_y: i16,
-    pub flags: u16,
-    pub next_index: u16,
-}
-
-impl BinDeserializer for VeleRaw {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> {
-        from_reader::<VeleRaw, {size_of::<VeleRaw>()}, R>(reader)
+pop_record! {
+    #[derive(Debug, Copy, Clone)]
+    pub struct VeleRaw {
+        pub sprite_index: u16,
+        pub coord_x: i16,
+        pub coord_y: i16,
+        pub flags: u16,
+        pub next_index: u16,
     }
 }
 
 /******************************************************************************/
 
-#[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
-pub struct VfraRaw {
-    pub index: u16,
-    pub width: u8,
-    pub height: u8,
-    pub f3: u8,
-    pub f4: u8,
-    pub next_vfra: u16,
-}
-
-impl BinDeserializer for VfraRaw {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> {
-        from_reader::<VfraRaw, {size_of::<VfraRaw>()}, R>(reader)
+pop_record! {
+    #[derive(Debug, Copy, Clone)]
+    pub struct VfraRaw {
+        pub index: u16,
+        pub width: u8,
+        pub height: u8,
+        pub f3: u8,
+        pub f4: u8,
+        pub next_vfra: u16,
     }
 }
 
 /******************************************************************************/
 
-#[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
-pub struct VstartRaw {
-    pub index: u16,
-    pub f1: u8,
-    pub f2: u8,
-}
-
-impl BinDeserializer for VstartRaw {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> {
-        from_reader::<VstartRaw, {size_of::<VstartRaw>()}, R>(reader)
+pop_record! {
+    #[derive(Debug, Copy, Clone)]
+    pub struct VstartRaw {
+        pub index: u16,
+        pub f1: u8,
+        pub f2: u8,
     }
 }
 
@@ -71,19 +56,20 @@ impl AnimationsData {
 
     pub fn from_reader<R: Read>(reader_vele: &mut R
                                , reader_vfra: &mut R
-                               , reader_vstart: &mut R) -> Self {
-        AnimationsData {
-            vele: VeleRaw::from_reader_vec(reader_vele),
-            vfra: VfraRaw::from_reader_vec(reader_vfra),
-            vstart: VstartRaw::from_reader_vec(reader_vstart),
-        }
+                               , reader_vstart: &mut R) -> Result<Self> {
+        Ok(AnimationsData {
+            vele: VeleRaw::from_reader_vec(reader_vele)?,
+            vfra: VfraRaw::from_reader_vec(reader_vfra)?,
+            vstart: VstartRaw::from_reader_vec(reader_vstart)?,
+        })
     }
 
-    pub fn from_path(path: &Path) -> Self {
-        let mut file_vele = File::options().read(true).open(path.join("VELE-0.ANI")).unwrap();
-        let mut file_vfra = File::options().read(true).open(path.join("VFRA-0.ANI")).unwrap();
-        let mut file_vstart = File::options().read(true).open(path.join("VSTART-0.ANI")).unwrap();
-        Self::from_reader(&mut file_vele, &mut file_vfra, &mut file_vstart)
+    pub fn from_fs(fs: &dyn ResourceFs, path: &Path) -> Result<Self> {
+        Ok(AnimationsData {
+            vele: VeleRaw::from_fs_vec(fs, &path.join("VELE-0.ANI"))?,
+            vfra: VfraRaw::from_fs_vec(fs, &path.join("VFRA-0.ANI"))?,
+            vstart: VstartRaw::from_fs_vec(fs, &path.join("VSTART-0.ANI"))?,
+        })
     }
 }
 
@@ -173,6 +159,27 @@ impl AnimationElement {
     }
 }
 
+impl ImagePos for AnimationElement {
+    fn pos_x(&self) -> isize {
+        self.coord_x as isize
+    }
+
+    fn pos_y(&self) -> isize {
+        self.coord_y as isize
+    }
+}
+
+// Both flags may be set, get_rotate reports only the horizontal one then
+impl ImageFlip for AnimationElement {
+    fn flip_horizontal(&self) -> bool {
+        (self.flags & 0x1) != 0
+    }
+
+    fn flip_vertical(&self) -> bool {
+        (self.flags & 0x2) != 0
+    }
+}
+
 impl AnimationFrame {
     pub fn get_permutations(&self, with_tribe: bool, with_type: bool) -> Vec<Vec<AnimationElement>> {
         let mut common_elems = Vec::new();
@@ -276,3 +283,38 @@ impl AnimationSequence {
 }
 
 /******************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pop::types::tests::assert_record_round_trip;
+    use crate::pop::types::Record;
+
+    #[test]
+    fn records_round_trip() {
+        assert_record_round_trip::<VeleRaw>();
+        assert_record_round_trip::<VfraRaw>();
+        assert_record_round_trip::<VstartRaw>();
+    }
+
+    #[test]
+    fn element_flip_flags() {
+        let elem = |flags| AnimationElement{sprite_index: 0, coord_x: 0, coord_y: 0, tribe: 0, flags, uvar5: 0, original_flags: flags};
+        let flips = |e: AnimationElement| (e.flip_horizontal(), e.flip_vertical());
+        assert_eq!(flips(elem(0x0)), (false, false));
+        assert_eq!(flips(elem(0x1)), (true, false));
+        assert_eq!(flips(elem(0x2)), (false, true));
+        assert_eq!(flips(elem(0x3)), (true, true));
+    }
+
+    #[test]
+    fn records_known_bytes() {
+        let vele = VeleRaw::decode(&[0x34, 0x12, 0xfe, 0xff, 0x05, 0x00, 0x01, 0x02, 0x07, 0x00]);
+        assert_eq!((vele.sprite_index, vele.coord_x, vele.coord_y), (0x1234, -2, 5));
+        assert_eq!((vele.flags, vele.next_index), (0x0201, 7));
+        let vfra = VfraRaw::decode(&[0x02, 0x01, 10, 20, 3, 4, 0x09, 0x00]);
+        assert_eq!((vfra.index, vfra.width, vfra.height, vfra.f3, vfra.f4, vfra.next_vfra), (0x0102, 10, 20, 3, 4, 9));
+        let vstart = VstartRaw::decode(&[0x05, 0x00, 1, 2]);
+        assert_eq!((vstart.index, vstart.f1, vstart.f2), (5, 1, 2));
+    }
+}
@@ -1,8 +1,10 @@
 use std::path::Path;
-use std::fs::File;
 use std::io::Read;
 
 use crate::pop::types::{ImageStorage, ImageStorageSource, AllocatorEqual, pal_image_allocator_1d_vertical};
+use crate::pop::error::Result;
+use crate::pop::fs::ResourceFs;
+use crate::pop::palette::Palette;
 
 /******************************************************************************/
 
@@ -24,23 +26,20 @@ pub fn read_textures<R: Read, P: ImageStorageSource, A: AllocatorEqual<P>>(alloc
     p
 }
 
-pub fn read_bl320<P: ImageStorageSource, A: AllocatorEqual<P>>(allocator: &A, path: &Path) -> P {
-    let mut file = File::options().read(true).open(path).unwrap();
-    let file_size = file.metadata().unwrap().len() as usize;
+pub fn read_bl320<P: ImageStorageSource, A: AllocatorEqual<P>>(fs: &dyn ResourceFs, allocator: &A, path: &Path) -> Result<P> {
     let width = 256;
     let height = 256;
-    read_textures(allocator, &mut file, file_size, width, height)
+    read_bl160(fs, width, height, allocator, path)
 }
 
-pub fn read_bl160<P: ImageStorageSource, A: AllocatorEqual<P>>(width: usize, height: usize, allocator: &A, path: &Path) -> P {
-    let mut file = File::options().read(true).open(path).unwrap();
-    let file_size = file.metadata().unwrap().len() as usize;
-    read_textures(allocator, &mut file, file_size, width, height)
+pub fn read_bl160<P: ImageStorageSource, A: AllocatorEqual<P>>(fs: &dyn ResourceFs, width: usize, height: usize, allocator: &A, path: &Path) -> Result<P> {
+    let data = fs.read(path)?;
+    Ok(read_textures(allocator, &mut data.as_slice(), data.len(), width, height))
 }
 
-pub fn make_bl320_texture_rgba(path: &Path, pal: &[u8]) -> (usize, usize, Vec<u8>) {
+pub fn make_bl320_texture_rgba(fs: &dyn ResourceFs, path: &Path, pal: &Palette) -> Result<(usize, usize, Vec<u8>)> {
     let allocator = pal_image_allocator_1d_vertical(pal);
-    let provider = read_bl320(&allocator, path);
+    let provider = read_bl320(fs, &allocator, path)?;
     let image = provider.get_image();
-    (image.width, image.height, image.data)
+    Ok((image.width, image.height, image.data))
 }
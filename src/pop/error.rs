@@ -0,0 +1,66 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/******************************************************************************/
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    BadMagic { expected: u32, found: u32 },
+    Truncated { expected: usize, found: usize },
+    OutOfRange { what: &'static str, index: usize, len: usize },
+    UnknownLandscapeType(u8),
+    BadFormat(String),
+    // Context wrappers, added by loaders which know where the data came from
+    At { offset: u64, source: Box<Error> },
+    File { path: PathBuf, source: Box<Error> },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn at(self, offset: u64) -> Self {
+        Error::At{offset, source: Box::new(self)}
+    }
+
+    pub fn in_file(self, path: &Path) -> Self {
+        Error::File{path: path.to_path_buf(), source: Box::new(self)}
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+            Error::BadMagic{expected, found} =>
+                write!(f, "bad magic 0x{found:08x}, expected 0x{expected:08x}"),
+            Error::Truncated{expected, found} =>
+                write!(f, "truncated record: got {found} of {expected} bytes"),
+            Error::OutOfRange{what, index, len} =>
+                write!(f, "{what} index {index} is out of range (len {len})"),
+            Error::UnknownLandscapeType(t) => write!(f, "unknown landscape type {t}"),
+            Error::BadFormat(s) => write!(f, "bad format: {s}"),
+            Error::At{offset, source} => write!(f, "at offset 0x{offset:x}: {source}"),
+            Error::File{path, source} => write!(f, "{}: {source}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::At{source, ..} | Error::File{source, ..} => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/******************************************************************************/
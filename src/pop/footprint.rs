@@ -0,0 +1,222 @@
+use crate::pop::level::Landscape;
+use crate::pop::types::Image;
+
+/******************************************************************************/
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FootprintCell {
+    Free,
+    Occupied,
+    Entrance,
+}
+
+impl FootprintCell {
+    pub fn from_raw(val: u8) -> Self {
+        match val {
+            0 => FootprintCell::Free,
+            2 => FootprintCell::Entrance,
+            _ => FootprintCell::Occupied,
+        }
+    }
+
+    pub fn is_occupied(&self) -> bool {
+        *self != FootprintCell::Free
+    }
+}
+
+// Values of cells in footprint images
+pub const FOOTPRINT_FREE: u8 = 0;
+pub const FOOTPRINT_OCCUPIED: u8 = 1;
+pub const FOOTPRINT_ENTRANCE: u8 = 2;
+pub const FOOTPRINT_ORIGIN: u8 = 3;
+
+/*
+ * Cells of landscape taken by a building. Origin is the cell where the
+ * building is placed, cells are stored row by row.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Footprint {
+    width: usize,
+    height: usize,
+    origin: (usize, usize),
+    cells: Vec<FootprintCell>,
+}
+
+impl Footprint {
+    pub fn new(width: usize, height: usize, origin: (usize, usize), cells: Vec<FootprintCell>) -> Self {
+        let mut cells = cells;
+        cells.resize(width * height, FootprintCell::Free);
+        Footprint{width, height, origin, cells}
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn origin(&self) -> (usize, usize) {
+        self.origin
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> FootprintCell {
+        if x >= self.width || y >= self.height {
+            return FootprintCell::Free;
+        }
+        self.cells[y * self.width + x]
+    }
+
+    pub fn occupied(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.cells.len())
+            .filter(|i| self.cells[*i].is_occupied())
+            .map(|i| (i % self.width, i / self.width))
+    }
+
+    pub fn entrance(&self) -> Option<(usize, usize)> {
+        self.cells.iter().position(|c| *c == FootprintCell::Entrance)
+            .map(|i| (i % self.width, i / self.width))
+    }
+
+    // Rotated by 90 degrees clockwise
+    pub fn rotated(&self) -> Self {
+        let width = self.height;
+        let height = self.width;
+        let mut cells = vec![FootprintCell::Free; width * height];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                cells[x * width + (self.height - 1 - y)] = self.cell(x, y);
+            }
+        }
+        let (ox, oy) = self.origin;
+        let origin = (self.height.saturating_sub(oy + 1), ox);
+        Footprint{width, height, origin, cells}
+    }
+
+    // Footprints for 4 orientations, starting from the original one
+    pub fn orientations(&self) -> Vec<Self> {
+        let mut res = vec![self.clone()];
+        for i in 0..3 {
+            let next = res[i].rotated();
+            res.push(next);
+        }
+        res
+    }
+
+    pub fn to_image(&self, cell_size: usize) -> Image {
+        let mut image = Image::alloc(self.width * cell_size, self.height * cell_size);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let val = match self.cell(x, y) {
+                    FootprintCell::Free => FOOTPRINT_FREE,
+                    FootprintCell::Occupied => FOOTPRINT_OCCUPIED,
+                    FootprintCell::Entrance => FOOTPRINT_ENTRANCE,
+                };
+                let val = if (x, y) == self.origin { FOOTPRINT_ORIGIN } else { val };
+                for j in 0..cell_size {
+                    let start = (y * cell_size + j) * image.width + x * cell_size;
+                    image.data[start..(start + cell_size)].fill(val);
+                }
+            }
+        }
+        image
+    }
+
+    /*
+     * Checks that all occupied cells are on land with equal height at their
+     * corners when origin is placed at cell, i.e. landscape.height[cell_y][cell_x].
+     * Landscape wraps around at its borders.
+     */
+    pub fn fits<const N: usize>(&self, landscape: &Landscape<N>, cell_x: usize, cell_y: usize) -> bool {
+        let (ox, oy) = self.origin;
+        let mut level: Option<u16> = None;
+        for (x, y) in self.occupied() {
+            let lx = (cell_x + N + x - ox) % N;
+            let ly = (cell_y + N + y - oy) % N;
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let h = landscape.height[(ly + dy) % N][(lx + dx) % N];
+                if h == 0 || level.is_some_and(|l| l != h) {
+                    return false;
+                }
+                level = Some(h);
+            }
+        }
+        true
+    }
+}
+
+/******************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use FootprintCell::*;
+
+    // Row 0: occupied, origin, free; row 1: free, entrance, free
+    fn footprint() -> Footprint {
+        Footprint::new(3, 2, (1, 0), vec![Occupied, Occupied, Free, Free, Entrance, Free])
+    }
+
+    fn flat_landscape(height: u16) -> Landscape<8> {
+        let mut landscape = Landscape::new();
+        landscape.height = [[height; 8]; 8];
+        landscape
+    }
+
+    #[test]
+    fn cells() {
+        let footprint = footprint();
+        assert_eq!(footprint.occupied().collect::<Vec<_>>(), vec![(0, 0), (1, 0), (1, 1)]);
+        assert_eq!(footprint.entrance(), Some((1, 1)));
+        assert_eq!(footprint.cell(3, 0), Free);
+    }
+
+    #[test]
+    fn rotations() {
+        let rotated = footprint().rotated();
+        let expected = Footprint::new(2, 3, (1, 1), vec![Free, Occupied, Entrance, Occupied, Free, Free]);
+        assert_eq!(rotated, expected);
+        let orientations = footprint().orientations();
+        assert_eq!(orientations.len(), 4);
+        assert_eq!(orientations[1], expected);
+        assert_eq!(orientations[3].rotated(), footprint());
+    }
+
+    #[test]
+    fn to_image() {
+        let image = footprint().to_image(2);
+        assert_eq!((image.width, image.height), (6, 4));
+        let rows: Vec<&[u8]> = image.data.chunks(image.width).collect();
+        assert_eq!(rows[0], &[1, 1, 3, 3, 0, 0]);
+        assert_eq!(rows[1], &[1, 1, 3, 3, 0, 0]);
+        assert_eq!(rows[2], &[0, 0, 2, 2, 0, 0]);
+        assert_eq!(rows[3], &[0, 0, 2, 2, 0, 0]);
+    }
+
+    #[test]
+    fn fits() {
+        let footprint = footprint();
+        // Placed at (4, 4) occupied cells have corners at x 3..=5, y 4..=6
+        let mut landscape = flat_landscape(100);
+        assert!(footprint.fits(&landscape, 4, 4));
+        landscape.height[4][6] = 0;
+        assert!(footprint.fits(&landscape, 4, 4));
+        landscape.height[6][5] = 0;
+        assert!(!footprint.fits(&landscape, 4, 4));
+        landscape.height[6][5] = 200;
+        assert!(!footprint.fits(&landscape, 4, 4));
+        assert!(!footprint.fits(&flat_landscape(0), 4, 4));
+    }
+
+    #[test]
+    fn fits_wraps_around() {
+        let footprint = footprint();
+        // Placed at (0, 7) corners are at x 7, 0, 1 and y 7, 0, 1
+        let mut landscape = flat_landscape(100);
+        assert!(footprint.fits(&landscape, 0, 7));
+        landscape.height[1][1] = 0;
+        assert!(!footprint.fits(&landscape, 0, 7));
+        assert!(footprint.fits(&landscape, 4, 4));
+    }
+}
@@ -0,0 +1,431 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, Cursor, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use zip::ZipArchive;
+
+use crate::pop::error::{Error, Result};
+
+/******************************************************************************/
+
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/*
+ * Source of game resources. Paths are relative to the root of game directory,
+ * e.g. "data/pal0-1.dat" or "levels/levl2001.hdr", and are matched ignoring case.
+ */
+pub trait ResourceFs {
+    fn open(&self, path: &Path) -> Result<Box<dyn ReadSeek + '_>>;
+
+    // Names of files in a directory
+    fn list(&self, dir: &Path) -> Result<Vec<String>>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.open(path).is_ok()
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let mut reader = self.open(path)?;
+        let mut vec = Vec::new();
+        reader.read_to_end(&mut vec).map_err(|e| Error::from(e).in_file(path))?;
+        Ok(vec)
+    }
+}
+
+impl<T: ResourceFs + ?Sized> ResourceFs for Box<T> {
+    fn open(&self, path: &Path) -> Result<Box<dyn ReadSeek + '_>> {
+        self.as_ref().open(path)
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<String>> {
+        self.as_ref().list(dir)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.as_ref().exists(path)
+    }
+}
+
+/*
+ * Reads file from resources and falls back to host file system, so that a path
+ * given by user may point either into game data or to any other file.
+ */
+pub fn read_resource_or_file(fs: &dyn ResourceFs, path: &Path) -> Result<Vec<u8>> {
+    match fs.read(path) {
+        Ok(data) => Ok(data),
+        Err(e) => match std::fs::read(path) {
+            Ok(data) => Ok(data),
+            Err(host) if host.kind() == ErrorKind::NotFound => Err(e),
+            Err(host) => Err(Error::from(host).in_file(path)),
+        },
+    }
+}
+
+fn not_found(path: &Path) -> Error {
+    Error::from(std::io::Error::from(ErrorKind::NotFound)).in_file(path)
+}
+
+/******************************************************************************/
+
+pub struct DirFs {
+    root: PathBuf,
+}
+
+impl DirFs {
+    pub fn new(root: &Path) -> Self {
+        DirFs{root: root.to_path_buf()}
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /*
+     * Finds an existing path which differs from requested only by case. If some
+     * component can't be found the rest of path is appended as is.
+     */
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        let full = self.root.join(path);
+        if full.exists() {
+            return full;
+        }
+        let mut res = PathBuf::new();
+        let mut components = full.components();
+        while let Some(c) = components.next() {
+            let name = match c {
+                Component::Normal(name) => name,
+                c => {
+                    res.push(c);
+                    continue;
+                }
+            };
+            if res.join(name).exists() {
+                res.push(name);
+                continue;
+            }
+            let found = Self::find_entry(&res, &name.to_string_lossy());
+            match found {
+                Some(entry) => res.push(entry),
+                None => {
+                    res.push(name);
+                    let rest = components.as_path();
+                    if !rest.as_os_str().is_empty() {
+                        res.push(rest);
+                    }
+                    break;
+                }
+            }
+        }
+        res
+    }
+
+    fn find_entry(dir: &Path, name: &str) -> Option<String> {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        std::fs::read_dir(dir).ok()?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .find(|e| e.eq_ignore_ascii_case(name))
+    }
+}
+
+impl ResourceFs for DirFs {
+    fn open(&self, path: &Path) -> Result<Box<dyn ReadSeek + '_>> {
+        let full = self.resolve(path);
+        let file = File::options().read(true).open(&full).map_err(|e| Error::from(e).in_file(&full))?;
+        Ok(Box::new(file))
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<String>> {
+        let full = self.resolve(dir);
+        let entries = std::fs::read_dir(&full).map_err(|e| Error::from(e).in_file(&full))?;
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(|e| Error::from(e).in_file(&full))?;
+            if entry.path().is_file() {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.resolve(path).exists()
+    }
+}
+
+/******************************************************************************/
+
+/*
+ * Game directory packed into a zip archive. Archive may contain the game directory
+ * itself at top level, in this case paths are resolved relative to it.
+ */
+pub struct ZipFs {
+    path: PathBuf,
+    archive: RefCell<ZipArchive<File>>,
+    // lowercase key -> name in archive
+    names: HashMap<String, String>,
+}
+
+impl ZipFs {
+    pub fn new(path: &Path) -> Result<Self> {
+        let file = File::options().read(true).open(path).map_err(|e| Error::from(e).in_file(path))?;
+        let archive = ZipArchive::new(file).map_err(|e| zip_error(e, path))?;
+        let keys: Vec<(String, String)> = archive.file_names()
+            .map(|name| (mem_key(Path::new(name)), name.to_string()))
+            .collect();
+        let root = Self::find_root(keys.iter().map(|(k, _)| k.as_str()));
+        let names = keys.into_iter()
+            .filter_map(|(key, name)| key.strip_prefix(&root).map(|k| (k.to_string(), name)))
+            .collect();
+        Ok(ZipFs{path: path.to_path_buf(), archive: RefCell::new(archive), names})
+    }
+
+    // Prefix of "data" directory, empty if it is at top level
+    fn find_root<'a, I: Iterator<Item = &'a str>>(keys: I) -> String {
+        let mut root: Option<String> = None;
+        for key in keys {
+            if key.starts_with("data/") {
+                return String::new();
+            }
+            if root.is_none() {
+                if let Some(pos) = key.find("/data/") {
+                    root = Some(key[..=pos].to_string());
+                }
+            }
+        }
+        root.unwrap_or_default()
+    }
+}
+
+fn zip_error(e: zip::result::ZipError, path: &Path) -> Error {
+    let e = match e {
+        zip::result::ZipError::Io(e) => e,
+        e => std::io::Error::new(ErrorKind::InvalidData, e.to_string()),
+    };
+    Error::from(e).in_file(path)
+}
+
+impl ResourceFs for ZipFs {
+    // Entry is decompressed into memory on each call, nothing is cached
+    fn open(&self, path: &Path) -> Result<Box<dyn ReadSeek + '_>> {
+        let name = self.names.get(&mem_key(path)).ok_or_else(|| not_found(path))?;
+        let mut archive = self.archive.borrow_mut();
+        let mut file = archive.by_name(name).map_err(|e| zip_error(e, &self.path.join(path)))?;
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data).map_err(|e| Error::from(e).in_file(&self.path.join(path)))?;
+        Ok(Box::new(Cursor::new(data)))
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<String>> {
+        let dir_key = mem_key(dir);
+        let prefix = if dir_key.is_empty() { dir_key } else { format!("{dir_key}/") };
+        let mut names: Vec<String> = self.names.iter()
+            .filter(|(key, _)| key.strip_prefix(&prefix).is_some_and(|rest| !rest.is_empty() && !rest.contains('/')))
+            .filter(|(_, name)| !name.ends_with('/'))
+            .filter_map(|(_, name)| Path::new(name).file_name().map(|n| n.to_string_lossy().to_string()))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.names.contains_key(&mem_key(path))
+    }
+}
+
+// Opens game directory or zip archive with game directory
+pub fn open_resource_fs(base: &Path) -> Result<Box<dyn ResourceFs>> {
+    let is_zip = base.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+    if is_zip && base.is_file() {
+        Ok(Box::new(ZipFs::new(base)?))
+    } else {
+        Ok(Box::new(DirFs::new(base)))
+    }
+}
+
+/******************************************************************************/
+
+// Files kept in memory, mostly useful for tests and generated resources
+#[derive(Default)]
+pub struct MemFs {
+    files: HashMap<String, (String, Vec<u8>)>,
+}
+
+fn mem_key(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy().to_lowercase()),
+            _ => None,
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+impl MemFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: &Path, data: Vec<u8>) {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        self.files.insert(mem_key(path), (name, data));
+    }
+}
+
+impl ResourceFs for MemFs {
+    fn open(&self, path: &Path) -> Result<Box<dyn ReadSeek + '_>> {
+        match self.files.get(&mem_key(path)) {
+            Some((_, data)) => Ok(Box::new(Cursor::new(data.as_slice()))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn list(&self, dir: &Path) -> Result<Vec<String>> {
+        let dir_key = mem_key(dir);
+        let prefix = if dir_key.is_empty() { dir_key } else { format!("{dir_key}/") };
+        let mut names: Vec<String> = self.files.iter()
+            .filter(|(key, _)| key.strip_prefix(&prefix).is_some_and(|rest| !rest.contains('/')))
+            .map(|(_, (name, _))| name.clone())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+}
+
+/******************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Directory removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("faithful-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn write(&self, path: &str, data: &[u8]) {
+            let path = self.0.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn dir_resolve_ignores_case() {
+        let dir = TempDir::new("dirfs-resolve");
+        dir.write("DATA/Pal0-1.DAT", &[1, 2, 3]);
+        let fs = DirFs::new(&dir.0);
+        assert_eq!(fs.resolve(Path::new("data/pal0-1.dat")), dir.0.join("DATA/Pal0-1.DAT"));
+        assert_eq!(fs.resolve(Path::new("DATA/Pal0-1.DAT")), dir.0.join("DATA/Pal0-1.DAT"));
+        // rest of path after the first missing component is kept as is
+        assert_eq!(fs.resolve(Path::new("data/Missing/pal0-1.dat")), dir.0.join("DATA/Missing/pal0-1.dat"));
+        assert_eq!(fs.resolve(Path::new("levels/pal0-1.dat")), dir.0.join("levels/pal0-1.dat"));
+        assert_eq!(fs.read(Path::new("Data/PAL0-1.dat")).unwrap(), vec![1, 2, 3]);
+        assert!(fs.exists(Path::new("data/pal0-1.dat")));
+        assert!(!fs.exists(Path::new("data/pal0-2.dat")));
+        assert!(fs.open(Path::new("data/pal0-2.dat")).is_err());
+    }
+
+    #[test]
+    fn dir_list_files_only() {
+        let dir = TempDir::new("dirfs-list");
+        dir.write("DATA/Pal0-1.DAT", &[1]);
+        dir.write("DATA/bl320-1.dat", &[2]);
+        dir.write("DATA/Sub/pal0-2.dat", &[3]);
+        let fs = DirFs::new(&dir.0);
+        assert_eq!(fs.list(Path::new("data")).unwrap(), vec!["Pal0-1.DAT", "bl320-1.dat"]);
+        assert!(fs.list(Path::new("levels")).is_err());
+    }
+
+    #[test]
+    fn mem_list_and_open() {
+        let mut fs = MemFs::new();
+        fs.insert(Path::new("Data/Pal0-1.DAT"), vec![1, 2]);
+        fs.insert(Path::new("data/sub/pal0-2.dat"), vec![3]);
+        fs.insert(Path::new("watdisp.dat"), vec![4]);
+        assert_eq!(fs.read(Path::new("DATA/pal0-1.dat")).unwrap(), vec![1, 2]);
+        assert!(fs.open(Path::new("data/pal0-2.dat")).is_err());
+        assert!(fs.exists(Path::new("data/SUB/pal0-2.dat")));
+        assert_eq!(fs.list(Path::new("DATA")).unwrap(), vec!["Pal0-1.DAT"]);
+        assert_eq!(fs.list(Path::new("data/sub")).unwrap(), vec!["pal0-2.dat"]);
+        assert_eq!(fs.list(Path::new("")).unwrap(), vec!["watdisp.dat"]);
+        assert!(fs.list(Path::new("levels")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn resource_or_host_file() {
+        let dir = TempDir::new("resource-or-file");
+        dir.write("sprites.dat", &[5, 6]);
+        let mut fs = MemFs::new();
+        fs.insert(Path::new("data/sprites.dat"), vec![1]);
+        assert_eq!(read_resource_or_file(&fs, Path::new("data/sprites.dat")).unwrap(), vec![1]);
+        let host = dir.0.join("sprites.dat");
+        assert_eq!(read_resource_or_file(&fs, &host).unwrap(), vec![5, 6]);
+        // error of resources is returned when there is no host file either
+        let missing = dir.0.join("missing.dat");
+        match read_resource_or_file(&fs, &missing) {
+            Err(Error::File{path, ..}) => assert_eq!(path, missing),
+            res => panic!("unexpected result {:?}", res.map(|d| d.len())),
+        }
+    }
+
+    #[test]
+    fn zip_find_root() {
+        let root = |keys: &[&str]| ZipFs::find_root(keys.iter().copied());
+        assert_eq!(root(&["data/pal0-1.dat", "levels/levl2001.dat"]), "");
+        assert_eq!(root(&["game", "game/data/pal0-1.dat", "game/levels/levl2001.dat"]), "game/");
+        assert_eq!(root(&["pop/game/data/pal0-1.dat"]), "pop/game/");
+        // data directory at top level takes precedence over nested one
+        assert_eq!(root(&["game/data/pal0-1.dat", "data/pal0-1.dat"]), "");
+        assert_eq!(root(&["readme.txt", "database/x.dat"]), "");
+    }
+
+    #[test]
+    fn zip_open_and_list() {
+        use std::io::Write;
+        use zip::write::{FileOptions, ZipWriter};
+
+        let dir = TempDir::new("zipfs");
+        let path = dir.0.join("pop.zip");
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.add_directory("Game/", options).unwrap();
+        writer.add_directory("Game/DATA/", options).unwrap();
+        writer.add_directory("Game/DATA/Sub/", options).unwrap();
+        writer.start_file("Game/DATA/Pal0-1.DAT", options).unwrap();
+        writer.write_all(&[1, 2, 3]).unwrap();
+        writer.start_file("Game/DATA/Sub/pal0-2.dat", options).unwrap();
+        writer.write_all(&[4]).unwrap();
+        writer.start_file("Game/LEVELS/levl2001.dat", options).unwrap();
+        writer.write_all(&[5]).unwrap();
+        writer.finish().unwrap();
+
+        let fs = ZipFs::new(&path).unwrap();
+        assert_eq!(fs.read(Path::new("data/pal0-1.dat")).unwrap(), vec![1, 2, 3]);
+        assert_eq!(fs.read(Path::new("DATA/PAL0-1.DAT")).unwrap(), vec![1, 2, 3]);
+        assert_eq!(fs.read(Path::new("levels/LEVL2001.DAT")).unwrap(), vec![5]);
+        assert!(fs.exists(Path::new("data/sub/pal0-2.dat")));
+        assert!(!fs.exists(Path::new("game/data/pal0-1.dat")));
+        assert!(fs.open(Path::new("data/pal0-2.dat")).is_err());
+        assert_eq!(fs.list(Path::new("data")).unwrap(), vec!["Pal0-1.DAT"]);
+        assert_eq!(fs.list(Path::new("Data/Sub")).unwrap(), vec!["pal0-2.dat"]);
+        assert!(fs.list(Path::new("")).unwrap().is_empty());
+    }
+}
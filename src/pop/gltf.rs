@@ -0,0 +1,222 @@
+use std::io::Write;
+
+use crate::pop::error::Result;
+use crate::pop::objects::{Object3D, Face};
+use crate::pop::json::json_string;
+
+/******************************************************************************/
+
+const GLB_MAGIC: u32 = 0x46546c67; // "glTF"
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4e4f534a;
+const CHUNK_BIN: u32 = 0x004e4942;
+
+const TARGET_ARRAY_BUFFER: u32 = 34962;
+const COMPONENT_FLOAT: u32 = 5126;
+const FILTER_NEAREST: u32 = 9728;
+
+const MATERIAL_TEXTURED: usize = 0;
+const MATERIAL_UNTEXTURED: usize = 1;
+
+pub struct GltfObject<'a> {
+    pub name: String,
+    pub object: &'a Object3D,
+    pub translation: [f32; 3],
+}
+
+/*
+ * Translations which place objects in rows of a square grid, cell size fits
+ * the largest object.
+ */
+pub fn grid_layout(objects: &[&Object3D]) -> Vec<[f32; 3]> {
+    let cell = objects.iter()
+        .filter_map(|object| object_bounds(object))
+        .map(|(min, max)| (max[0] - min[0]).max(max[2] - min[2]))
+        .fold(0.0f32, f32::max) * 1.25;
+    let columns = (objects.len() as f32).sqrt().ceil().max(1.0) as usize;
+    (0..objects.len())
+        .map(|i| [(i % columns) as f32 * cell, 0.0, (i / columns) as f32 * cell])
+        .collect()
+}
+
+fn object_bounds(object: &Object3D) -> Option<([f32; 3], [f32; 3])> {
+    let scale = object.model_scale();
+    let mut bounds: Option<([f32; 3], [f32; 3])> = None;
+    for face in object.iter_face() {
+        for v in face.vertices() {
+            let p = [v.x * scale, v.y * scale, v.z * scale];
+            bounds = Some(match bounds {
+                Some((min, max)) => (
+                    [min[0].min(p[0]), min[1].min(p[1]), min[2].min(p[2])],
+                    [max[0].max(p[0]), max[1].max(p[1]), max[2].max(p[2])],
+                ),
+                None => (p, p),
+            });
+        }
+    }
+    bounds
+}
+
+/******************************************************************************/
+
+#[derive(Default)]
+struct Primitive {
+    positions: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+}
+
+impl Primitive {
+    fn push_vertex(&mut self, face: &Face, index: usize, scale: f32, textured: bool) {
+        let v = &face.vertex[index];
+        self.positions.push([v.x * scale, v.y * scale, v.z * scale]);
+        if textured {
+            let (u, v) = face.atlas_uv(index).unwrap_or_default();
+            self.uvs.push([u, v]);
+        }
+    }
+}
+
+#[derive(Default)]
+struct GlbBuilder {
+    bin: Vec<u8>,
+    buffer_views: Vec<String>,
+    accessors: Vec<String>,
+    meshes: Vec<String>,
+    nodes: Vec<String>,
+}
+
+impl GlbBuilder {
+    fn push_view(&mut self, data: &[u8], target: Option<u32>) -> usize {
+        while !self.bin.len().is_multiple_of(4) {
+            self.bin.push(0);
+        }
+        let offset = self.bin.len();
+        self.bin.extend_from_slice(data);
+        let target = target.map(|t| format!(", \"target\": {t}")).unwrap_or_default();
+        self.buffer_views.push(format!("{{\"buffer\": 0, \"byteOffset\": {offset}, \"byteLength\": {}{target}}}", data.len()));
+        self.buffer_views.len() - 1
+    }
+
+    fn push_vec3(&mut self, values: &[[f32; 3]]) -> usize {
+        let data: Vec<u8> = values.iter().flatten().flat_map(|v| v.to_le_bytes()).collect();
+        let view = self.push_view(&data, Some(TARGET_ARRAY_BUFFER));
+        let mut min = values[0];
+        let mut max = values[0];
+        for v in values {
+            for i in 0..3 {
+                min[i] = min[i].min(v[i]);
+                max[i] = max[i].max(v[i]);
+            }
+        }
+        self.accessors.push(format!("{{\"bufferView\": {view}, \"componentType\": {COMPONENT_FLOAT}, \"count\": {}, \"type\": \"VEC3\", \"min\": [{}, {}, {}], \"max\": [{}, {}, {}]}}"
+                                    , values.len(), min[0], min[1], min[2], max[0], max[1], max[2]));
+        self.accessors.len() - 1
+    }
+
+    fn push_vec2(&mut self, values: &[[f32; 2]]) -> usize {
+        let data: Vec<u8> = values.iter().flatten().flat_map(|v| v.to_le_bytes()).collect();
+        let view = self.push_view(&data, Some(TARGET_ARRAY_BUFFER));
+        self.accessors.push(format!("{{\"bufferView\": {view}, \"componentType\": {COMPONENT_FLOAT}, \"count\": {}, \"type\": \"VEC2\"}}", values.len()));
+        self.accessors.len() - 1
+    }
+
+    // Quads are split into two triangles in the same way as in pop_obj_view
+    fn push_object(&mut self, obj: &GltfObject) {
+        let scale = obj.object.model_scale();
+        let mut textured = Primitive::default();
+        let mut untextured = Primitive::default();
+        for face in obj.object.iter_face() {
+            let has_texture = face.has_texture();
+            let primitive = if has_texture { &mut textured } else { &mut untextured };
+            let indices: &[usize] = if face.vertex_num == 4 { &[0, 1, 2, 2, 3, 0] } else { &[0, 1, 2] };
+            for &i in indices {
+                primitive.push_vertex(&face, i, scale, has_texture);
+            }
+        }
+        let mut primitives = Vec::new();
+        if !textured.positions.is_empty() {
+            let position = self.push_vec3(&textured.positions);
+            let uv = self.push_vec2(&textured.uvs);
+            primitives.push(format!("{{\"attributes\": {{\"POSITION\": {position}, \"TEXCOORD_0\": {uv}}}, \"material\": {MATERIAL_TEXTURED}}}"));
+        }
+        if !untextured.positions.is_empty() {
+            let position = self.push_vec3(&untextured.positions);
+            primitives.push(format!("{{\"attributes\": {{\"POSITION\": {position}}}, \"material\": {MATERIAL_UNTEXTURED}}}"));
+        }
+        let name = json_string(&obj.name);
+        let [x, y, z] = obj.translation;
+        if primitives.is_empty() {
+            self.nodes.push(format!("{{\"name\": {name}, \"translation\": [{x}, {y}, {z}]}}"));
+        } else {
+            self.meshes.push(format!("{{\"name\": {name}, \"primitives\": [{}]}}", primitives.join(", ")));
+            self.nodes.push(format!("{{\"name\": {name}, \"mesh\": {}, \"translation\": [{x}, {y}, {z}]}}", self.meshes.len() - 1));
+        }
+    }
+
+    fn to_json(&self, texture_view: Option<usize>) -> String {
+        let textured = match texture_view {
+            Some(_) => "{\"name\": \"bl320\", \"pbrMetallicRoughness\": {\"baseColorTexture\": {\"index\": 0}, \"metallicFactor\": 0, \"roughnessFactor\": 1}, \"alphaMode\": \"MASK\", \"doubleSided\": true}",
+            None => "{\"name\": \"bl320\", \"pbrMetallicRoughness\": {\"metallicFactor\": 0, \"roughnessFactor\": 1}, \"doubleSided\": true}",
+        };
+        let untextured = "{\"name\": \"untextured\", \"pbrMetallicRoughness\": {\"baseColorFactor\": [1, 1, 0, 1], \"metallicFactor\": 0, \"roughnessFactor\": 1}, \"doubleSided\": true}";
+        let scene_nodes: Vec<String> = (0..self.nodes.len()).map(|i| i.to_string()).collect();
+        let mut json = String::from("{\"asset\": {\"version\": \"2.0\", \"generator\": \"faithful\"}");
+        json.push_str(&format!(", \"scene\": 0, \"scenes\": [{{\"nodes\": [{}]}}]", scene_nodes.join(", ")));
+        json.push_str(&format!(", \"nodes\": [{}]", self.nodes.join(", ")));
+        if !self.meshes.is_empty() {
+            json.push_str(&format!(", \"meshes\": [{}]", self.meshes.join(", ")));
+        }
+        json.push_str(&format!(", \"materials\": [{textured}, {untextured}]"));
+        if let Some(view) = texture_view {
+            json.push_str(&format!(", \"samplers\": [{{\"magFilter\": {FILTER_NEAREST}, \"minFilter\": {FILTER_NEAREST}}}]"));
+            json.push_str(&format!(", \"images\": [{{\"bufferView\": {view}, \"mimeType\": \"image/png\"}}]"));
+            json.push_str(", \"textures\": [{\"sampler\": 0, \"source\": 0}]");
+        }
+        if !self.accessors.is_empty() {
+            json.push_str(&format!(", \"accessors\": [{}]", self.accessors.join(", ")));
+        }
+        if !self.buffer_views.is_empty() {
+            json.push_str(&format!(", \"bufferViews\": [{}]", self.buffer_views.join(", ")));
+            json.push_str(&format!(", \"buffers\": [{{\"byteLength\": {}}}]", self.bin.len()));
+        }
+        json.push('}');
+        json
+    }
+}
+
+/*
+ * Binary glTF 2.0 export, every object is a node of the scene. Texture is BL320
+ * atlas in PNG format, UVs are mapped into it with Face::atlas_uv.
+ */
+pub fn write_glb<W: Write>(writer: &mut W, objects: &[GltfObject], texture_png: Option<&[u8]>) -> Result<()> {
+    let mut builder = GlbBuilder::default();
+    for object in objects {
+        builder.push_object(object);
+    }
+    let texture_view = texture_png.map(|png| builder.push_view(png, None));
+    let mut json = builder.to_json(texture_view).into_bytes();
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    let mut bin = builder.bin;
+    while !bin.len().is_multiple_of(4) {
+        bin.push(0);
+    }
+    let bin_chunk_len = if bin.is_empty() { 0 } else { 8 + bin.len() };
+    let total_len = 12 + 8 + json.len() + bin_chunk_len;
+
+    writer.write_all(&GLB_MAGIC.to_le_bytes())?;
+    writer.write_all(&GLB_VERSION.to_le_bytes())?;
+    writer.write_all(&(total_len as u32).to_le_bytes())?;
+    writer.write_all(&(json.len() as u32).to_le_bytes())?;
+    writer.write_all(&CHUNK_JSON.to_le_bytes())?;
+    writer.write_all(&json)?;
+    if !bin.is_empty() {
+        writer.write_all(&(bin.len() as u32).to_le_bytes())?;
+        writer.write_all(&CHUNK_BIN.to_le_bytes())?;
+        writer.write_all(&bin)?;
+    }
+    Ok(())
+}
+
+/******************************************************************************/
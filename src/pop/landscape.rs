@@ -7,18 +7,17 @@ pub mod disp;
 
 /******************************************************************************/
 
-use crate::pop::level::LevelRes;
+use crate::pop::level::{LevelRes, Sunlight};
+use crate::pop::palette::Palette;
 
 use crate::pop::landscape::common::{LandPos, LandscapeFull};
 use crate::pop::landscape::land::texture_land;
 
-pub fn draw_texture_u8(pal: &[u8], width: usize, texture: &[u8]) -> Vec<u8> {
+pub fn draw_texture_u8(pal: &Palette, width: usize, texture: &[u8]) -> Vec<u8> {
     let mut img = vec![0u8; 3 * width * width];
     for i in 0..width {
         for j in 0..width {
-            let palette_index = texture[(i*width + j) as usize] as usize;
-            let palette_index = palette_index.min(127) * 4;
-            let buf: &[u8] = &pal[palette_index..=(palette_index+2)];
+            let buf = pal.rgb(texture[i*width + j]);
             let img_index = 3 * width * i + 3 * j;
             img[img_index] = buf[0];
             img[img_index+1] = buf[1];
@@ -28,11 +27,15 @@ pub fn draw_texture_u8(pal: &[u8], width: usize, texture: &[u8]) -> Vec<u8> {
     img
 }
 
+// sunlight overrides level's own sunlight if set
 pub fn make_texture_land(level_res: &LevelRes
+                         , sunlight: Option<&Sunlight>
                          , _tex_move: Option<(u32, u32)>) -> Vec<u8> {
     let land_size = level_res.landscape.land_size();
     let params_globe = &level_res.params;
-    let land = LandPos::from_landscape_sun(&level_res.landscape);
+    let sunlight = sunlight.unwrap_or(&level_res.sunlight);
+    let mut land = LandPos::from_landscape_sun(&level_res.landscape, sunlight);
+    LandPos::set_flags(&mut land, &level_res.land_flags);
     let landscape = LandscapeFull::new(land_size, land);
     texture_land(land_size, &landscape, params_globe).data
 }
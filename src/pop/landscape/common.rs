@@ -1,5 +1,5 @@
 use std::marker::PhantomData;
-use crate::pop::level::Landscape;
+use crate::pop::level::{Landscape, LandLayer, Sunlight};
 
 /******************************************************************************/
 
@@ -40,10 +40,19 @@ impl LandPos {
         v
     }
 
-    pub fn from_landscape_sun<const N: usize>(landscape: &Landscape<N>) -> Vec<LandPos> {
-        let sunlight_var_1 = 0x93;
-        let sunlight_var_2 = 0x93;
-        let sunlight_var_3 = 0x93;
+    pub fn set_flags<const N: usize>(land: &mut [LandPos], flags: &LandLayer<N>) {
+        for i in 0..N {
+            let p = i * N;
+            for j in 0..N {
+                land[p+j].flags = flags.data[i][j] as u32;
+            }
+        }
+    }
+
+    pub fn from_landscape_sun<const N: usize>(landscape: &Landscape<N>, sunlight: &Sunlight) -> Vec<LandPos> {
+        let sunlight_var_1 = sunlight.v1 as i32;
+        let sunlight_var_2 = sunlight.v2 as i32;
+        let sunlight_var_3 = sunlight.v3 as i32;
         let mut v = Self::from_landscape(landscape);
         for i in 0..N {
             let p = i * N;
@@ -1,9 +1,11 @@
 use std::path::{Path, PathBuf};
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek};
+use std::io::{Read, Write, Seek, SeekFrom};
 
-use crate::pop::types::BinDeserializer;
-use crate::pop::units::{UnitRaw, TribeConfigRaw};
+use crate::pop::types::{BinDeserializer, BinSerializer, Record, open_file, create_file, read_record, pop_record};
+use crate::pop::units::{UnitRaw, TribeConfigRaw, TribeConfig};
+use crate::pop::error::{Error, Result};
+use crate::pop::fs::ResourceFs;
+use crate::pop::palette::{Palette, FadeTable, BlendTable};
 
 /******************************************************************************/
 
@@ -13,6 +15,7 @@ pub struct LevelPaths {
     pub bigf0: PathBuf,
     pub cliff0: PathBuf,
     pub fade0: PathBuf,
+    pub ghost0: PathBuf,
     pub bl320: PathBuf,
     pub bl160: PathBuf,
     pub watdisp: PathBuf,
@@ -33,15 +36,15 @@ impl LevelPaths {
             bigf0: mk_based_path(base, format!("bigf0-{key}.dat")),
             cliff0: mk_based_path(base, format!("cliff0-{key}.dat")),
             fade0: mk_based_path(base, format!("fade0-{key}.dat")),
+            ghost0: mk_based_path(base, format!("ghost0-{key}.dat")),
             bl320: mk_based_path(base, format!("BL320-{key_upper}.DAT")),
             bl160: mk_based_path(base, format!("BL160-{key_upper}.DAT")),
             watdisp: mk_based_path(base, "watdisp.dat".to_string()),
         }
     }
 
-    pub fn from_default_dir(base: &Path, key: &str) -> Self {
-        let data_dir = base.join("data");
-        Self::from_base(&data_dir, key)
+    pub fn from_default_dir(key: &str) -> Self {
+        Self::from_base(Path::new("data"), key)
     }
 
     pub fn dat_path(base: &Path, num: u8) -> PathBuf {
@@ -75,9 +78,8 @@ impl ObjectPaths {
         }
     }
 
-    pub fn from_default_dir(base: &Path, key: &str) -> Self {
-        let data_dir = base.join("objects");
-        Self::from_base(&data_dir, key)
+    pub fn from_default_dir(key: &str) -> Self {
+        Self::from_base(Path::new("objects"), key)
     }
 }
 
@@ -95,10 +97,17 @@ impl Sunlight {
         Sunlight {v1, v2, v3}
     }
 
-    pub fn from_reader<R: Read>(reader: &mut R) -> Self {
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
         let mut buf = [0u8; 3];
-        reader.read_exact(&mut buf).unwrap();
-        Self::new(buf[0], buf[1], buf[2])
+        if !read_record(reader, &mut buf)? {
+            return Err(Error::Truncated{expected: buf.len(), found: 0});
+        }
+        Ok(Self::new(buf[0], buf[1], buf[2]))
+    }
+
+    pub fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&[self.v1, self.v2, self.v3])?;
+        Ok(())
     }
 }
 
@@ -108,112 +117,366 @@ pub struct LevelRes {
     pub paths: LevelPaths,
     pub params: GlobeTextureParams,
     pub landscape: Landscape<128>,
+    pub layer_1: LandLayer<128>,
+    pub layer_2: LandLayer<128>,
+    pub land_flags: LandLayer<128>,
     pub tribes: Vec<TribeConfigRaw>,
     pub sunlight: Sunlight,
     pub units: Vec<UnitRaw>,
+    pub trailer: [u8; LEVEL_TRAILER_SIZE],
+    pub header: LevelHeader,
+}
+
+const LEVEL_UNITS_NUM: usize = 2000;
+pub const LEVEL_TRAILER_SIZE: usize = 0x96;
+// File offsets of tables in level .dat, after landscape and land layers
+pub const LEVEL_TRIBES_OFFSET: usize = 0x14000;
+pub const LEVEL_SUNLIGHT_OFFSET: usize = LEVEL_TRIBES_OFFSET + 4 * <TribeConfigRaw as Record>::SIZE;
+pub const LEVEL_UNITS_OFFSET: usize = LEVEL_SUNLIGHT_OFFSET + 3;
+
+struct LevelDat {
+    landscape: Landscape<128>,
+    layer_1: LandLayer<128>,
+    layer_2: LandLayer<128>,
+    land_flags: LandLayer<128>,
+    tribes: Vec<TribeConfigRaw>,
+    sunlight: Sunlight,
+    units: Vec<UnitRaw>,
+    trailer: [u8; LEVEL_TRAILER_SIZE],
 }
 
 impl LevelRes {
-    pub fn new(base: &Path, level_num: u8, level_type_opt: Option<&str>) -> LevelRes {
-        let level_dir = base.join("levels");
-        let (level_path, level_type) = read_level(&level_dir, level_num);
+    pub fn new(fs: &dyn ResourceFs, level_num: u8, level_type_opt: Option<&str>) -> Result<LevelRes> {
+        let level_dir = Path::new("levels");
+        let level_path = LevelPaths::dat_path(level_dir, level_num);
+        let hdr_path = LevelPaths::hdr_path(level_dir, level_num);
+        let header = LevelHeader::from_fs(fs, &hdr_path)?;
+        let level_type = header.landscape_type().map_err(|e| e.at(96).in_file(&hdr_path))?;
 
         let paths = match level_type_opt {
-            Some(v) => LevelPaths::from_default_dir(base, v),
-            None => LevelPaths::from_default_dir(base, &level_type),
+            Some(v) => LevelPaths::from_default_dir(v),
+            None => LevelPaths::from_default_dir(&level_type),
         };
 
-        let mut file = File::options().read(true).open(&level_path).unwrap();
-        let landscape = Landscape::from_reader(&mut file);
-        file.seek(std::io::SeekFrom::Start(0x8000)).unwrap();
-        //read 0x4000
-        file.seek(std::io::SeekFrom::Current(0x4000)).unwrap();
-        //read 0x4000
-        file.seek(std::io::SeekFrom::Current(0x4000)).unwrap();
-        //read 0x4000 (land flags)
-        file.seek(std::io::SeekFrom::Current(0x4000)).unwrap();
-        let mut tribes = Vec::new();
-        for _ in 0..4 {
-            tribes.push(TribeConfigRaw::from_reader(&mut file).unwrap());
-        }
-        let sunlight = Sunlight::from_reader(&mut file);
-        //read units (up to 5500 * 100)
-        let units = UnitRaw::from_reader_vec(&mut file);
-        //read 0x96
-        file.seek(std::io::SeekFrom::Current(0x96)).unwrap();
-        let params = GlobeTextureParams::from_level(&paths);
-        LevelRes {
+        let mut file = fs.open(&level_path)?;
+        let dat = Self::read_dat(&mut file).map_err(|e| e.in_file(&level_path))?;
+        let params = GlobeTextureParams::from_level(fs, &paths)?;
+        Ok(LevelRes {
             paths,
             params,
-            landscape,
-            tribes,
-            sunlight,
-            units,
+            landscape: dat.landscape,
+            layer_1: dat.layer_1,
+            layer_2: dat.layer_2,
+            land_flags: dat.land_flags,
+            tribes: dat.tribes,
+            sunlight: dat.sunlight,
+            units: dat.units,
+            trailer: dat.trailer,
+            header,
+        })
+    }
+
+    // Writes levl2XXX.dat and levl2XXX.hdr into dir
+    pub fn save(&self, dir: &Path, level_num: u8) -> Result<()> {
+        let dat_path = LevelPaths::dat_path(dir, level_num);
+        let mut file = create_file(&dat_path)?;
+        self.write_dat(&mut file).map_err(|e| e.in_file(&dat_path))?;
+        let hdr_path = LevelPaths::hdr_path(dir, level_num);
+        self.header.to_file(&hdr_path)
+    }
+
+    pub fn tribe_configs(&self) -> Vec<TribeConfig> {
+        self.tribes.iter().enumerate().map(|(i, raw)| {
+            let index = i as u8;
+            TribeConfig::from_raw(index, raw, self.header.is_computer(index), self.header.raw.allies[i])
+        }).collect()
+    }
+
+    pub fn set_tribe_config(&mut self, index: usize, config: &TribeConfig) -> Result<()> {
+        let len = self.tribes.len().min(self.header.raw.allies.len());
+        if index >= len {
+            return Err(Error::OutOfRange{what: "tribe", index, len});
         }
+        self.tribes[index] = config.to_raw();
+        self.header.raw.allies[index] = config.allies;
+        self.header.set_computer(index as u8, config.is_computer);
+        Ok(())
+    }
+
+    fn write_dat<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.landscape.to_writer(writer)?;
+        self.layer_1.to_writer(writer)?;
+        self.layer_2.to_writer(writer)?;
+        self.land_flags.to_writer(writer)?;
+        TribeConfigRaw::to_writer_vec(&self.tribes, writer)?;
+        self.sunlight.to_writer(writer)?;
+        UnitRaw::to_writer_vec(&self.units, writer)?;
+        writer.write_all(&self.trailer)?;
+        Ok(())
+    }
+
+    fn read_dat<R: Read + Seek>(file: &mut R) -> Result<LevelDat> {
+        let landscape = Landscape::from_reader(file).map_err(|e| e.at(0))?;
+        let layer_1 = LandLayer::from_reader(file).map_err(|e| e.at(0x8000))?;
+        let layer_2 = LandLayer::from_reader(file).map_err(|e| e.at(0xc000))?;
+        let land_flags = LandLayer::from_reader(file).map_err(|e| e.at(0x10000))?;
+        let offset = file.stream_position()?;
+        let mut tribes = Vec::new();
+        for i in 0..4 {
+            let tribe_offset = offset + (i * TribeConfigRaw::SIZE) as u64;
+            match TribeConfigRaw::from_reader(file) {
+                Ok(Some(tribe)) => tribes.push(tribe),
+                Ok(None) => {
+                    let e = Error::Truncated{expected: TribeConfigRaw::SIZE, found: 0};
+                    return Err(e.at(tribe_offset));
+                },
+                Err(e) => return Err(e.at(tribe_offset)),
+            }
+        }
+        let offset = file.stream_position()?;
+        let sunlight = Sunlight::from_reader(file).map_err(|e| e.at(offset))?;
+        //read units (2000 * 55)
+        let offset = file.stream_position()?;
+        let units_size = LEVEL_UNITS_NUM * UnitRaw::SIZE;
+        let units = UnitRaw::from_reader_vec(&mut file.take(units_size as u64))
+            .map_err(|e| e.at(offset))?;
+        let offset = file.stream_position()?;
+        let mut trailer = [0u8; LEVEL_TRAILER_SIZE];
+        if !read_record(file, &mut trailer).map_err(|e| e.at(offset))? {
+            return Err(Error::Truncated{expected: LEVEL_TRAILER_SIZE, found: 0}.at(offset));
+        }
+        Ok(LevelDat{landscape, layer_1, layer_2, land_flags, tribes, sunlight, units, trailer})
     }
 }
 
-pub fn read_level(base: &Path, num: u8) -> (PathBuf, String) {
+pub fn read_level(fs: &dyn ResourceFs, base: &Path, num: u8) -> Result<(PathBuf, String)> {
     let dat_path = LevelPaths::dat_path(base, num);
     let hdr_path = LevelPaths::hdr_path(base, num);
-    let s = read_landscape_type(&hdr_path);
-    (dat_path, s)
+    let s = read_landscape_type(fs, &hdr_path)?;
+    Ok((dat_path, s))
 }
 
 /******************************************************************************/
 
-pub fn read_landscape_type(hdr_path: &Path) -> String {
-    let hdr_data = read_bin(hdr_path);
-    if hdr_data.len() < 70 {
-        panic!("Hdr is too small {}", hdr_data.len())
+#[derive(Debug, Clone)]
+pub struct LevelInfo {
+    pub num: u8,
+    pub name: String,
+    pub landscape_type: String,
+    pub dat_size: u64,
+}
+
+/*
+ * Levels found in "levels" directory. Level is listed only if both .dat and
+ * .hdr files exist and can be read, other levels are skipped. Level numbers
+ * are u8 as everywhere else, so custom levels 256-999 are skipped as well.
+ */
+pub struct LevelCatalog {
+    pub levels: Vec<LevelInfo>,
+}
+
+impl LevelCatalog {
+    pub fn scan(fs: &dyn ResourceFs) -> Result<Self> {
+        let level_dir = Path::new("levels");
+        let names: Vec<String> = fs.list(level_dir)?.iter().map(|s| s.to_lowercase()).collect();
+        let prefix = "levl2";
+        let mut levels = Vec::new();
+        for name in &names {
+            let num = match name.strip_prefix(prefix).and_then(|s| s.strip_suffix(".dat")) {
+                Some(s) if s.len() == 3 => match s.parse::<u8>() {
+                    Ok(num) => num,
+                    Err(_) => continue,
+                },
+                _ => continue,
+            };
+            if !names.contains(&format!("{prefix}{num:03}.hdr")) {
+                continue;
+            }
+            let hdr_path = LevelPaths::hdr_path(level_dir, num);
+            let header = match LevelHeader::from_fs(fs, &hdr_path) {
+                Ok(header) => header,
+                Err(_) => continue,
+            };
+            let landscape_type = match header.landscape_type() {
+                Ok(t) => t,
+                Err(_) => continue,
+            };
+            let dat_path = LevelPaths::dat_path(level_dir, num);
+            let dat_size = match fs.open(&dat_path).and_then(|mut f| Ok(f.seek(SeekFrom::End(0))?)) {
+                Ok(size) => size,
+                Err(_) => continue,
+            };
+            levels.push(LevelInfo{num, name: header.name(), landscape_type, dat_size});
+        }
+        levels.sort_by_key(|l| l.num);
+        Ok(LevelCatalog{levels})
+    }
+
+    pub fn get(&self, num: u8) -> Option<&LevelInfo> {
+        self.levels.iter().find(|l| l.num == num)
+    }
+
+    // Level after num, wraps around
+    pub fn next(&self, num: u8) -> Option<u8> {
+        self.levels.iter().find(|l| l.num > num).or(self.levels.first()).map(|l| l.num)
+    }
+
+    // Level before num, wraps around
+    pub fn prev(&self, num: u8) -> Option<u8> {
+        self.levels.iter().rev().find(|l| l.num < num).or(self.levels.last()).map(|l| l.num)
+    }
+}
+
+/******************************************************************************/
+
+pop_record! {
+    /*
+     * Fixed part of level header (levl2XXX.hdr). Only fields with known meaning
+     * are named, other bytes are kept as is.
+     */
+    #[derive(Debug, Copy, Clone)]
+    pub struct LevelHeaderRaw {
+        pub unknown: [u8; 56],
+        pub name: [u8; 32],
+        pub num_players: u8,
+        /*
+         * Meaning of the next fields is not confirmed by a format reference:
+         * computer_players is read as tribe indices of computer players with 0
+         * for an empty slot, allies as a mask of allied tribes for each tribe.
+         */
+        pub computer_players: [u8; 3],
+        pub allies: [u8; 4],
+        pub landscape_type: u8,
+        pub objects_bank: u8,
+        pub flags: u8,
+        pub pad: u8,
+        pub markers: [u16; 256],
+        pub start_pos: u16,
+        pub start_angle: u16,
+    }
+}
+
+pub const LEVEL_HEADER_SIZE: usize = <LevelHeaderRaw as Record>::SIZE;
+
+/*
+ * Level header with bytes which follow the fixed record, they are kept so that
+ * longer headers are written back unchanged.
+ *
+ * There is no known objective field: the fixed record is the same for all
+ * levels and win conditions of campaign levels are not stored in it, so the
+ * objective is left in the unknown bytes.
+ */
+#[derive(Debug, Clone)]
+pub struct LevelHeader {
+    pub raw: LevelHeaderRaw,
+    pub trailing: Vec<u8>,
+}
+
+impl LevelHeader {
+    pub fn name(&self) -> String {
+        let name = &self.raw.name;
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        String::from_utf8_lossy(&name[..len]).into_owned()
+    }
+
+    pub fn landscape_type(&self) -> Result<String> {
+        landscape_type_name(self.raw.landscape_type)
+    }
+
+    // Blue tribe (0) is a player, so zero means an empty computer slot
+    pub fn is_computer(&self, tribe_index: u8) -> bool {
+        tribe_index != 0 && self.raw.computer_players.contains(&tribe_index)
+    }
+
+    pub fn set_computer(&mut self, tribe_index: u8, val: bool) {
+        if tribe_index == 0 || self.is_computer(tribe_index) == val {
+            return;
+        }
+        let (from, to) = if val { (0, tribe_index) } else { (tribe_index, 0) };
+        if let Some(slot) = self.raw.computer_players.iter_mut().find(|v| **v == from) {
+            *slot = to;
+        }
+    }
+}
+
+impl BinDeserializer for LevelHeader {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Option<Self>> where Self: Sized {
+        let raw = match LevelHeaderRaw::from_reader(reader)? {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        let mut trailing = Vec::new();
+        reader.read_to_end(&mut trailing)?;
+        Ok(Some(Self{raw, trailing}))
+    }
+}
+
+impl BinSerializer for LevelHeader {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.raw.to_writer(writer)?;
+        writer.write_all(&self.trailing)?;
+        Ok(())
+    }
+}
+
+pub fn read_landscape_type(fs: &dyn ResourceFs, hdr_path: &Path) -> Result<String> {
+    let hdr_data = fs.read(hdr_path)?;
+    if hdr_data.len() <= 96 {
+        return Err(Error::Truncated{expected: 97, found: hdr_data.len()}.in_file(hdr_path));
     }
-    let type_int = hdr_data[96];
+    landscape_type_name(hdr_data[96]).map_err(|e| e.at(96).in_file(hdr_path))
+}
+
+fn landscape_type_name(type_int: u8) -> Result<String> {
     match type_int {
         0 ..= 9 => {
             let v = 0x30 + type_int;
-            std::char::from_u32(v as u32).unwrap().to_string().to_lowercase()
+            Ok(std::char::from_u32(v as u32).unwrap().to_string().to_lowercase())
         },
         i if i < 36 => {
             let v = 0x41 + (type_int - 10);
-            std::char::from_u32(v as u32).unwrap().to_string().to_lowercase()
+            Ok(std::char::from_u32(v as u32).unwrap().to_string().to_lowercase())
         },
-        _ => panic!("Wrong landscape type {type_int:?}")
+        _ => Err(Error::UnknownLandscapeType(type_int))
     }
 }
 
 /******************************************************************************/
 
-pub fn read_bin(path: &Path) -> Vec<u8> {
-    let mut f = OpenOptions::new().read(true).open(path).unwrap();
+pub fn read_bin(path: &Path) -> Result<Vec<u8>> {
+    let mut f = open_file(path)?;
     let mut vec = Vec::new();
-    f.read_to_end(&mut vec).unwrap();
-    vec
+    f.read_to_end(&mut vec).map_err(|e| Error::from(e).in_file(path))?;
+    Ok(vec)
 }
 
 #[allow(dead_code)]
-fn read_bin16(path: &Path) -> Vec<u16> {
-    let buf = read_bin(path);
+fn read_bin16(path: &Path) -> Result<Vec<u16>> {
+    let buf = read_bin(path)?;
     let mut vec = vec![0; buf.len() / 2];
     for (i, n) in (0..).zip(buf.chunks(2).take(vec.len())) {
         if n.len() == 2 {
             vec[i] = u16::from_le_bytes([n[0], n[1]]);
         }
     }
-    vec
+    Ok(vec)
 }
 
-fn read_bin_i8(path: &Path) -> Vec<i8> {
-    let buf = read_bin(path);
+fn read_bin_i8(fs: &dyn ResourceFs, path: &Path) -> Result<Vec<i8>> {
+    let buf = fs.read(path)?;
     let mut v = std::mem::ManuallyDrop::new(buf);
     let p = v.as_mut_ptr();
     let len = v.len();
     let cap = v.capacity();
-    unsafe { Vec::from_raw_parts(p as *mut i8, len, cap) }
+    Ok(unsafe { Vec::from_raw_parts(p as *mut i8, len, cap) })
 }
 
-fn read_disp(path: &Path) -> Vec<i8> {
-    let mut disp = read_bin_i8(path);
+fn read_disp(fs: &dyn ResourceFs, path: &Path) -> Result<Vec<i8>> {
+    let mut disp = read_bin_i8(fs, path)?;
     let width = 256;
+    if disp.len() < width * width {
+        return Err(Error::Truncated{expected: width * width, found: disp.len()}.in_file(path));
+    }
     for i in 0..width {
         for j in 0..(width/2 - 1) {
             let n = i*width + j;
@@ -221,11 +484,11 @@ fn read_disp(path: &Path) -> Vec<i8> {
             disp.swap(n, n1);
         }
     }
-    disp
+    Ok(disp)
 }
 
-pub fn read_pal(paths: &LevelPaths) -> Vec<u8> {
-    read_bin(&paths.palette)
+pub fn read_pal(fs: &dyn ResourceFs, paths: &LevelPaths) -> Result<Palette> {
+    Palette::from_fs(fs, &paths.palette)
 }
 
 /******************************************************************************/
@@ -235,22 +498,32 @@ pub struct GlobeTextureParams {
     pub cliff0: Vec<u8>,
     pub bigf0: Vec<u8>,
     pub fade0: Vec<u8>,
+    pub ghost0: Vec<u8>,
     pub static_landscape_array: Vec<u16>,
-    pub palette: Vec<u8>,
+    pub palette: Palette,
     pub watdisp: Vec<u8>,
 }
 
 impl GlobeTextureParams {
-    pub fn from_level(paths: &LevelPaths) -> Self {
-        Self {
-            bigf0: read_bin(&paths.bigf0),
-            cliff0: read_bin(&paths.cliff0),
-            disp0: read_disp(&paths.disp0),
-            fade0: read_bin(&paths.fade0),
+    pub fn from_level(fs: &dyn ResourceFs, paths: &LevelPaths) -> Result<Self> {
+        Ok(Self {
+            bigf0: fs.read(&paths.bigf0)?,
+            cliff0: fs.read(&paths.cliff0)?,
+            disp0: read_disp(fs, &paths.disp0)?,
+            fade0: fs.read(&paths.fade0)?,
+            ghost0: fs.read(&paths.ghost0)?,
             static_landscape_array: Self::make_static_array(),
-            palette: read_bin(&paths.palette),
-            watdisp: read_bin(&paths.watdisp),
-        }
+            palette: read_pal(fs, paths)?,
+            watdisp: fs.read(&paths.watdisp)?,
+        })
+    }
+
+    pub fn fade_table(&self) -> Result<FadeTable> {
+        FadeTable::from_bytes(&self.fade0)
+    }
+
+    pub fn blend_table(&self) -> Result<BlendTable> {
+        BlendTable::from_bytes(&self.ghost0)
     }
 
     pub fn make_static_array() -> Vec<u16> {
@@ -284,34 +557,37 @@ impl<const N: usize> Landscape<N> {
     }
 
     fn flip(&mut self) {
-        let width = N;
-        for i in 0..width {
-            for j in 0..(width/2 - 1) {
-                let n1 = (width-1) - j;
-                let v = self.height[j][i];
-                self.height[j][i] = self.height[n1][i];
-                self.height[n1][i] = v;
-            }
-        }
+        flip_rows(&mut self.height);
     }
 
-    pub fn from_reader<R: Read>(reader: &mut R) -> Self {
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
         let mut s = Self::new();
-        let mut buf = Vec::new();
-        let _file_size = reader.read_to_end(&mut buf);
-        for (i, n) in (0..).zip(buf.chunks(2).take(N*N)) {
-            if n.len() == 2 {
-                let val = u16::from_le_bytes([n[0], n[1]]);
-                s.height[i%N][i/N] = val;
-            }
+        let mut buf = vec![0u8; N * N * 2];
+        if !read_record(reader, &mut buf)? {
+            return Err(Error::Truncated{expected: buf.len(), found: 0});
+        }
+        for (i, n) in (0..).zip(buf.chunks(2)) {
+            let val = u16::from_le_bytes([n[0], n[1]]);
+            s.height[i%N][i/N] = val;
         }
         s.flip();
-        s
+        Ok(s)
     }
 
-    pub fn from_file(path: &Path) -> Self {
-        let mut file = File::options().read(true).open(path).unwrap();
-        Self::from_reader(&mut file)
+    pub fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut height = self.height;
+        flip_rows(&mut height);
+        let mut buf = vec![0u8; N * N * 2];
+        for (i, n) in buf.chunks_mut(2).enumerate() {
+            n.copy_from_slice(&height[i%N][i/N].to_le_bytes());
+        }
+        writer.write_all(&buf)?;
+        Ok(())
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let mut file = open_file(path)?;
+        Self::from_reader(&mut file).map_err(|e| e.in_file(path))
     }
 
     pub fn is_land_adj(&self, i: usize, j: usize) -> bool {
@@ -362,3 +638,159 @@ impl<const N: usize> Default for Landscape<N> {
 }
 
 /******************************************************************************/
+
+/*
+ * Per cell byte layer of a level file. It is stored in the same column major order
+ * as heights, so it is transposed and flipped the same way to match Landscape.
+ */
+pub struct LandLayer<const N: usize> {
+    pub data: [[u8; N]; N],
+}
+
+impl<const N: usize> LandLayer<N> {
+    pub fn new() -> Self {
+        Self{data: [[0u8; N]; N]}
+    }
+
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut s = Self::new();
+        let mut buf = vec![0u8; N * N];
+        if !read_record(reader, &mut buf)? {
+            return Err(Error::Truncated{expected: buf.len(), found: 0});
+        }
+        for (i, val) in buf.into_iter().enumerate() {
+            s.data[i%N][i/N] = val;
+        }
+        flip_rows(&mut s.data);
+        Ok(s)
+    }
+
+    pub fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut data = self.data;
+        flip_rows(&mut data);
+        let mut buf = vec![0u8; N * N];
+        for (i, val) in buf.iter_mut().enumerate() {
+            *val = data[i%N][i/N];
+        }
+        writer.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+impl<const N: usize> Default for LandLayer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Row of a landscape which holds row of a level file
+pub fn flip_row(width: usize, row: usize) -> usize {
+    if row < (width/2 - 1) || row > width/2 {
+        (width-1) - row
+    } else {
+        row
+    }
+}
+
+fn flip_rows<T, const N: usize>(data: &mut [[T; N]; N]) {
+    let width = N;
+    for j in 0..(width/2 - 1) {
+        data.swap(j, (width-1) - j);
+    }
+}
+
+/******************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pop::fs::MemFs;
+    use crate::pop::types::tests::synthetic_bytes;
+
+    const DAT_SIZE: usize = LEVEL_UNITS_OFFSET + LEVEL_UNITS_NUM * <UnitRaw as Record>::SIZE + LEVEL_TRAILER_SIZE;
+
+    fn synthetic_level_fs(dat: &[u8], hdr: &[u8]) -> MemFs {
+        let mut fs = MemFs::new();
+        fs.insert(Path::new("levels/levl2001.dat"), dat.to_vec());
+        fs.insert(Path::new("levels/levl2001.hdr"), hdr.to_vec());
+        let paths = LevelPaths::from_default_dir("1");
+        fs.insert(&paths.palette, synthetic_bytes(1024, 1));
+        fs.insert(&paths.disp0, synthetic_bytes(256 * 256, 2));
+        fs.insert(&paths.bigf0, synthetic_bytes(1024, 3));
+        fs.insert(&paths.cliff0, synthetic_bytes(1024, 4));
+        fs.insert(&paths.fade0, synthetic_bytes(256 * 64, 5));
+        fs.insert(&paths.ghost0, synthetic_bytes(256 * 256, 9));
+        fs.insert(&paths.watdisp, synthetic_bytes(1024, 6));
+        fs
+    }
+
+    #[test]
+    fn level_round_trip() {
+        let dat = synthetic_bytes(DAT_SIZE, 7);
+        // header with bytes after the fixed record
+        let mut hdr = synthetic_bytes(LEVEL_HEADER_SIZE + 20, 8);
+        hdr[96] = 1;
+        let fs = synthetic_level_fs(&dat, &hdr);
+        let level = LevelRes::new(&fs, 1, None).unwrap();
+        // first value of file is the first column of the last row
+        assert_eq!(level.landscape.height[127][0], u16::from_le_bytes([dat[0], dat[1]]));
+        assert_eq!(level.land_flags.data[127][0], dat[0x10000]);
+
+        let dir = std::env::temp_dir().join(format!("faithful-level-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        level.save(&dir, 1).unwrap();
+        let saved_dat = std::fs::read(dir.join("levl2001.dat")).unwrap();
+        let saved_hdr = std::fs::read(dir.join("levl2001.hdr")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(saved_dat, dat);
+        assert_eq!(saved_hdr, hdr);
+
+        let fs = synthetic_level_fs(&saved_dat, &saved_hdr);
+        let reloaded = LevelRes::new(&fs, 1, None).unwrap();
+        assert_eq!(reloaded.landscape.height, level.landscape.height);
+        assert_eq!(reloaded.layer_1.data, level.layer_1.data);
+    }
+
+    fn catalog_header(name: &str) -> Vec<u8> {
+        let mut hdr = vec![0u8; LEVEL_HEADER_SIZE];
+        hdr[56..(56 + name.len())].copy_from_slice(name.as_bytes());
+        hdr[96] = 2;
+        hdr
+    }
+
+    #[test]
+    fn catalog_scan() {
+        let mut fs = MemFs::new();
+        for (num, name) in [(3, "three"), (1, "one"), (7, "seven")] {
+            fs.insert(&LevelPaths::dat_path(Path::new("levels"), num), vec![0; num as usize]);
+            fs.insert(&LevelPaths::hdr_path(Path::new("levels"), num), catalog_header(name));
+        }
+        // missing .hdr, truncated .hdr, number which does not fit u8 and other files
+        fs.insert(Path::new("levels/levl2005.dat"), vec![0; 5]);
+        fs.insert(Path::new("levels/levl2009.dat"), vec![0; 9]);
+        fs.insert(Path::new("levels/levl2009.hdr"), vec![0; 10]);
+        fs.insert(Path::new("levels/levl2300.dat"), vec![0; 3]);
+        fs.insert(Path::new("levels/levl2300.hdr"), catalog_header("big"));
+        fs.insert(Path::new("levels/readme.txt"), vec![0; 3]);
+
+        let catalog = LevelCatalog::scan(&fs).unwrap();
+        let levels: Vec<(u8, &str, &str, u64)> = catalog.levels.iter()
+            .map(|l| (l.num, l.name.as_str(), l.landscape_type.as_str(), l.dat_size))
+            .collect();
+        assert_eq!(levels, vec![(1, "one", "2", 1), (3, "three", "2", 3), (7, "seven", "2", 7)]);
+        assert_eq!(catalog.get(3).map(|l| l.num), Some(3));
+        assert!(catalog.get(5).is_none());
+
+        assert_eq!(catalog.next(1), Some(3));
+        assert_eq!(catalog.next(3), Some(7));
+        assert_eq!(catalog.next(5), Some(7));
+        assert_eq!(catalog.next(7), Some(1));
+        assert_eq!(catalog.prev(7), Some(3));
+        assert_eq!(catalog.prev(3), Some(1));
+        assert_eq!(catalog.prev(1), Some(7));
+
+        let empty = LevelCatalog::scan(&MemFs::new()).unwrap();
+        assert_eq!(empty.next(1), None);
+        assert_eq!(empty.prev(1), None);
+    }
+}
@@ -1,9 +1,18 @@
+pub mod error;
+pub mod fs;
 pub mod types;
 pub mod level;
 pub mod landscape;
+pub mod palette;
 pub mod pls;
 pub mod bl320;
 pub mod objects;
+pub mod footprint;
+pub mod wavefront;
+pub mod gltf;
+pub mod json;
 pub mod psfb;
 pub mod units;
 pub mod animation;
+
+pub use error::{Error, Result};
@@ -1,119 +1,211 @@
-use std::path::Path;
-use std::io::Read;
-use core::mem::size_of;
 use core::slice::Iter;
 
-use crate::pop::types::{BinDeserializer, from_reader};
+use crate::pop::types::{BinDeserializer, pop_record};
+use crate::pop::error::{Error, Result};
 use crate::pop::level::ObjectPaths;
+use crate::pop::fs::ResourceFs;
+use crate::pop::footprint::{Footprint, FootprintCell};
 
 /******************************************************************************/
 
-#[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
-pub struct ObjectRaw {
-    flags: u16,
-    facs_num: u16,
-    pnts_num: u16,
-    f1: u8,
-    morph_index: u8,
-    f2: u32,
-    coord_scale: u32,
-    facs_ptr: u32,
-    facs_ptr_end: u32,
-    pnts_ptr: u32,
-    pnts_ptr_end: u32,
-    f4: i16,
-    f5: i16,
-    f6: i16,
-    f7: u16,
-    f8: u16,
-    f9: u16,
-    shapes_index: u8,
-    u1: u8,
-    f10: u16,
-    f11: u16,
-    f12: u16,
-    f13: u16,
-}
-
-impl BinDeserializer for ObjectRaw {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> {
-        from_reader::<ObjectRaw, {size_of::<ObjectRaw>()}, R>(reader)
+pop_record! {
+    #[derive(Debug, Copy, Clone)]
+    pub struct ObjectRaw {
+        flags: u16,
+        facs_num: u16,
+        pnts_num: u16,
+        f1: u8,
+        morph_index: u8,
+        f2: u32,
+        coord_scale: u32,
+        facs_ptr: u32,
+        facs_ptr_end: u32,
+        pnts_ptr: u32,
+        pnts_ptr_end: u32,
+        f4: i16,
+        f5: i16,
+        f6: i16,
+        f7: u16,
+        f8: u16,
+        f9: u16,
+        shapes_index: u8,
+        u1: u8,
+        f10: u16,
+        f11: u16,
+        f12: u16,
+        f13: u16,
     }
 }
 
 /******************************************************************************/
 
-#[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
-pub struct Shape {
-    x1: u8,
-    y1: u8,
-    x2: u8,
-    y2: u8,
-    unknown: [u8; 40],
-    ptr: u32,
+pop_record! {
+    #[derive(Debug, Copy, Clone)]
+    pub struct Shape {
+        x1: u8,
+        y1: u8,
+        x2: u8,
+        y2: u8,
+        unknown: [u8; 40],
+        ptr: u32,
+    }
 }
 
-impl BinDeserializer for Shape {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> {
-        from_reader::<Self, {size_of::<Self>()}, R>(reader)
+pub const SHAPE_CELLS_MAX: usize = 40;
+
+impl Shape {
+    /*
+     * Layout of SHAPES records is not documented anywhere, it is a guess from
+     * the data and not checked against the game: x1, y1 are size of footprint
+     * grid, x2, y2 are the origin cell and the unknown block holds x1 * y1
+     * cells row by row: 0 - free, 2 - entrance, other values are occupied.
+     * Grid which does not fit the block is an error, origin outside of the
+     * grid is moved to its nearest cell.
+     */
+    pub fn footprint(&self) -> Result<Footprint> {
+        let width = self.x1 as usize;
+        let height = self.y1 as usize;
+        if width * height > SHAPE_CELLS_MAX {
+            return Err(Error::OutOfRange{what: "shape cells", index: width * height, len: SHAPE_CELLS_MAX});
+        }
+        let cells = self.unknown[0..(width * height)].iter()
+            .map(|v| FootprintCell::from_raw(*v))
+            .collect();
+        let origin = ((self.x2 as usize).min(width.saturating_sub(1)), (self.y2 as usize).min(height.saturating_sub(1)));
+        Ok(Footprint::new(width, height, origin, cells))
     }
 }
 
 /******************************************************************************/
 
-#[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
-pub struct PointRaw {
-    x: i16,
-    y: i16,
-    z: i16,
+pop_record! {
+    #[derive(Debug, Copy, Clone)]
+    pub struct PointRaw {
+        x: i16,
+        y: i16,
+        z: i16,
+    }
+}
+
+/******************************************************************************/
+
+pop_record! {
+    #[derive(Debug, Copy, Clone)]
+    pub struct FaceRaw {
+        f0: u16,
+        tex_index: i16,
+        flags1: i16,
+        num_points: u8,
+        f11: u8,
+        point_1_u: u32,
+        point_1_v: u32,
+        point_2_u: u32,
+        point_2_v: u32,
+        point_3_u: u32,
+        point_3_v: u32,
+        point_4_u: u32,
+        point_4_v: u32,
+        point_1: u16,
+        point_2: u16,
+        point_3: u16,
+        point_4: u16,
+        f6: u16,
+        ff1: u16,
+        ff2: u16,
+        ff3: u16,
+        ff4: u16,
+        f8: u8,
+        flags2: u8,
+    }
 }
 
-impl BinDeserializer for PointRaw {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> {
-        from_reader::<Self, {size_of::<Self>()}, R>(reader)
+impl FaceRaw {
+    // Face points are indices into points of its object
+    fn check_points(&self, len: usize) -> Result<()> {
+        let num_points = std::cmp::min(self.num_points as usize, 4);
+        let points = [self.point_1, self.point_2, self.point_3, self.point_4];
+        for point in &points[0..num_points.max(3)] {
+            if *point as usize >= len {
+                return Err(Error::OutOfRange{what: "face point", index: *point as usize, len});
+            }
+        }
+        Ok(())
     }
 }
 
 /******************************************************************************/
 
-#[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
-pub struct FaceRaw {
-    f0: u16,
-    tex_index: i16,
-    flags1: i16,
-    num_points: u8,
-    f11: u8,
-    point_1_u: u32,
-    point_1_v: u32,
-    point_2_u: u32,
-    point_2_v: u32,
-    point_3_u: u32,
-    point_3_v: u32,
-    point_4_u: u32,
-    point_4_v: u32,
-    point_1: u16,
-    point_2: u16,
-    point_3: u16,
-    point_4: u16,
-    f6: u16,
-    ff1: u16,
-    ff2: u16,
-    ff3: u16,
-    ff4: u16,
-    f8: u8,
-    flags2: u8,
-}
-
-impl BinDeserializer for FaceRaw {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> {
-        from_reader::<Self, {size_of::<Self>()}, R>(reader)
+/*
+ * There is no known description of morph0-X.dat, the layout below is a guess
+ * which is not checked against the game, so morphs are experimental. The file
+ * is read as point tables stored one after another in order of morph_index
+ * starting from 1. Object with non zero morph_index morphs from its points to
+ * the points of table morph_index with the same indices, objects with the same
+ * morph_index share a table and so must have the same number of points. File
+ * which does not split exactly into tables used by objects is rejected, so data
+ * with other layout gives an error instead of wrong points.
+ */
+#[derive(Debug, Clone)]
+pub struct MorphTarget {
+    pub index: u8,
+    points: Vec<PointRaw>,
+}
+
+impl MorphTarget {
+    // Splits morph points into tables, table of morph_index k is at k - 1
+    pub fn create_tables(objects: &[ObjectRaw], morph_points: &[PointRaw]) -> Result<Vec<Vec<PointRaw>>> {
+        let count = objects.iter().map(|o| o.morph_index as usize).max().unwrap_or(0);
+        let mut sizes: Vec<Option<usize>> = vec![None; count];
+        for object in objects.iter().filter(|o| o.morph_index != 0) {
+            let size = object.pnts_ptr_end.saturating_sub(object.pnts_ptr) as usize;
+            let table_size = &mut sizes[object.morph_index as usize - 1];
+            match *table_size {
+                Some(other) if other != size => return Err(Error::BadFormat(format!(
+                    "objects with morph index {} have {} and {} points", object.morph_index, other, size))),
+                _ => *table_size = Some(size),
+            }
+        }
+        let mut tables = Vec::with_capacity(count);
+        let mut start = 0;
+        for (i, size) in sizes.into_iter().enumerate() {
+            let size = size.ok_or_else(|| Error::BadFormat(format!("morph table {} is not used by objects", i + 1)))?;
+            let end = start + size;
+            if end > morph_points.len() {
+                return Err(Error::Truncated{expected: end, found: morph_points.len()});
+            }
+            tables.push(morph_points[start..end].to_vec());
+            start = end;
+        }
+        if start != morph_points.len() {
+            return Err(Error::BadFormat(format!("morph tables take {} points of {}", start, morph_points.len())));
+        }
+        Ok(tables)
+    }
+
+    pub fn create(object: &ObjectRaw, tables: &[Vec<PointRaw>]) -> Result<Option<Self>> {
+        if object.morph_index == 0 {
+            return Ok(None);
+        }
+        let points = get_indexed("morph table", tables, object.morph_index as usize)?;
+        Ok(Some(MorphTarget{index: object.morph_index, points: points.clone()}))
+    }
+
+    pub fn points(&self) -> &[PointRaw] {
+        &self.points
+    }
+
+    // Points at step of steps, step 0 is the source shape and step == steps is the target
+    pub fn interpolate(&self, from: &[PointRaw], step: usize, steps: usize) -> Vec<PointRaw> {
+        let t = if steps == 0 { 1.0 } else { step.min(steps) as f32 / steps as f32 };
+        let lerp = |a: i16, b: i16| (a as f32 + (b as f32 - a as f32) * t).round() as i16;
+        from.iter().zip(&self.points).map(|(a, b)| {
+            PointRaw{x: lerp(a.x, b.x), y: lerp(a.y, b.y), z: lerp(a.z, b.z)}
+        }).collect()
     }
 }
 
+pub const MORPH_STEPS: usize = 8;
+
 /******************************************************************************/
 
 const XYZ_SCALE: f32 = 1.0 / 300.0;
@@ -159,45 +251,107 @@ impl Face {
     pub fn new(texture_index: i16, vertex_num: usize) -> Self {
         Face{texture_index, vertex_num, vertex: [Vertex::default(); 4]}
     }
+
+    pub fn has_texture(&self) -> bool {
+        (0..(TEXTURE_ATLAS_COLUMNS * TEXTURE_ATLAS_ROWS) as i16).contains(&self.texture_index)
+    }
+
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertex[0..self.vertex_num]
+    }
+
+    // UV of vertex in BL320 atlas, v goes down as rows of image
+    pub fn atlas_uv(&self, vertex: usize) -> Option<(f32, f32)> {
+        if !self.has_texture() {
+            return None;
+        }
+        let v = &self.vertex[vertex];
+        let row = self.texture_index as usize / TEXTURE_ATLAS_COLUMNS;
+        let column = self.texture_index as usize % TEXTURE_ATLAS_COLUMNS;
+        let hor_k = 1.0 / TEXTURE_ATLAS_COLUMNS as f32;
+        let ver_k = 1.0 / TEXTURE_ATLAS_ROWS as f32;
+        Some((hor_k * (column as f32 + v.u), ver_k * (row as f32 + v.v)))
+    }
 }
 
+// BL320 texture drawn by pal_image_allocator_1d_vertical is 8 columns and 32 rows of face textures
+pub const TEXTURE_ATLAS_COLUMNS: usize = 8;
+pub const TEXTURE_ATLAS_ROWS: usize = 32;
+
 /******************************************************************************/
 
 #[derive(Debug)]
 pub struct Object3D {
+    index: usize,
     object: ObjectRaw,
     faces: Vec<FaceRaw>,
     points: Vec<PointRaw>,
+    morph: Option<MorphTarget>,
 }
 
 impl Object3D {
-    pub fn create(object: &ObjectRaw, faces: &[FaceRaw], points: &[PointRaw]) -> Self {
-        let mut object_3d = Object3D{object: *object, faces: Vec::new(), points: Vec::new()};
+    // index is index of object in OBJS0 file
+    pub fn create(index: usize, object: &ObjectRaw, faces: &[FaceRaw], points: &[PointRaw]) -> Result<Self> {
+        let mut object_3d = Object3D{index, object: *object, faces: Vec::new(), points: Vec::new(), morph: None};
         for i in object.pnts_ptr..object.pnts_ptr_end {
-            object_3d.points.push(points[i as usize-1]);
+            object_3d.points.push(*get_indexed("point", points, i as usize)?);
         }
         for i in object.facs_ptr..object.facs_ptr_end {
-            object_3d.faces.push(faces[i as usize-1]);
+            let face = get_indexed("face", faces, i as usize)?;
+            face.check_points(object_3d.points.len())?;
+            object_3d.faces.push(*face);
         }
-        object_3d
+        Ok(object_3d)
     }
 
-    pub fn create_objects(objects: &[ObjectRaw], faces: &[FaceRaw], points: &[PointRaw]) -> Vec<Self> {
+    pub fn create_objects(objects: &[ObjectRaw], faces: &[FaceRaw], points: &[PointRaw]) -> Result<Vec<Self>> {
         let mut objects_3d = Vec::new();
-        for object in objects {
+        for (index, object) in objects.iter().enumerate() {
             if object.facs_num > 0 {
-                objects_3d.push(Self::create(object, faces, points));
+                objects_3d.push(Self::create(index, object, faces, points)?);
             }
         }
-        objects_3d
+        Ok(objects_3d)
+    }
+
+    // objects are all objects of OBJS0 file, they define sizes of morph tables
+    pub fn create_morphs(objects_3d: &mut [Self], objects: &[ObjectRaw], morph_points: &[PointRaw]) -> Result<()> {
+        let tables = MorphTarget::create_tables(objects, morph_points)?;
+        for object_3d in objects_3d {
+            object_3d.morph = MorphTarget::create(&object_3d.object, &tables)?;
+        }
+        Ok(())
+    }
+
+    // Morph file is optional
+    pub fn from_fs(fs: &dyn ResourceFs, bank_num: &str) -> Result<Vec<Self>> {
+        let paths = ObjectPaths::from_default_dir(bank_num);
+        let objects = ObjectRaw::from_fs_vec(fs, &paths.objs0_dat)?;
+        let points = PointRaw::from_fs_vec(fs, &paths.pnts0)?;
+        let faces = FaceRaw::from_fs_vec(fs, &paths.facs0)?;
+        let mut objects_3d = Self::create_objects(&objects, &faces, &points)?;
+        if fs.exists(&paths.morph0) {
+            let morph_points = PointRaw::from_fs_vec(fs, &paths.morph0)?;
+            Self::create_morphs(&mut objects_3d, &objects, &morph_points)?;
+        }
+        Ok(objects_3d)
     }
 
-    pub fn from_file(base: &Path, bank_num: &str) -> Vec<Self> {
-        let paths = ObjectPaths::from_default_dir(base, bank_num);
-        let objects = ObjectRaw::from_file_vec(&paths.objs0_dat);
-        let points = PointRaw::from_file_vec(&paths.pnts0);
-        let faces = FaceRaw::from_file_vec(&paths.facs0);
-        Self::create_objects(&objects, &faces, &points)
+    pub fn morph(&self) -> Option<&MorphTarget> {
+        self.morph.as_ref()
+    }
+
+    // Copy of object with points of morph step, object without morph is copied as is
+    pub fn morphed(&self, step: usize, steps: usize) -> Self {
+        let points = match &self.morph {
+            Some(morph) => morph.interpolate(&self.points, step, steps),
+            None => self.points.clone(),
+        };
+        Object3D{index: self.index, object: self.object, faces: self.faces.clone(), points, morph: None}
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
     }
 
     pub fn iter_face(&self) -> FaceIter<Iter<FaceRaw>> {
@@ -207,6 +361,21 @@ impl Object3D {
     pub fn coord_scale(&self) -> f32 {
         self.object.coord_scale as f32
     }
+
+    pub fn shapes_index(&self) -> usize {
+        self.object.shapes_index as usize
+    }
+
+    // Factor to get model coordinates from vertex coordinates
+    pub fn model_scale(&self) -> f32 {
+        self.coord_scale() / 300.0
+    }
+}
+
+// Object pointers into points/faces tables are 1-based
+fn get_indexed<'a, T>(what: &'static str, items: &'a [T], index: usize) -> Result<&'a T> {
+    index.checked_sub(1).and_then(|i| items.get(i))
+        .ok_or(Error::OutOfRange{what, index, len: items.len()})
 }
 
 /******************************************************************************/
@@ -236,3 +405,130 @@ impl<'a, I> Iterator for FaceIter<'a, I> where I: Iterator<Item = &'a FaceRaw> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pop::types::Record;
+    use crate::pop::types::tests::assert_record_round_trip;
+
+    #[test]
+    fn records_round_trip() {
+        assert_record_round_trip::<ObjectRaw>();
+        assert_record_round_trip::<PointRaw>();
+        assert_record_round_trip::<FaceRaw>();
+        assert_record_round_trip::<Shape>();
+    }
+
+    fn morph_object(morph_index: u8, pnts_ptr: u32, pnts_ptr_end: u32) -> ObjectRaw {
+        let mut object = ObjectRaw::decode(&[0; ObjectRaw::SIZE]);
+        object.morph_index = morph_index;
+        object.pnts_ptr = pnts_ptr;
+        object.pnts_ptr_end = pnts_ptr_end;
+        object
+    }
+
+    fn point(x: i16) -> PointRaw {
+        PointRaw{x, y: 0, z: 0}
+    }
+
+    #[test]
+    fn morph_tables_by_index() {
+        // Table 1 has 2 points, table 2 is shared and has 3 points
+        let objects = [
+            morph_object(2, 1, 4),
+            morph_object(0, 4, 10),
+            morph_object(1, 10, 12),
+            morph_object(2, 12, 15),
+        ];
+        let morph_points: Vec<PointRaw> = (0..5).map(point).collect();
+        let tables = MorphTarget::create_tables(&objects, &morph_points).unwrap();
+        let xs: Vec<Vec<i16>> = tables.iter().map(|t| t.iter().map(|p| p.x).collect()).collect();
+        assert_eq!(xs, vec![vec![0, 1], vec![2, 3, 4]]);
+
+        assert!(MorphTarget::create(&objects[1], &tables).unwrap().is_none());
+        let morph = MorphTarget::create(&objects[3], &tables).unwrap().unwrap();
+        assert_eq!(morph.index, 2);
+        assert_eq!(morph.points().iter().map(|p| p.x).collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        let from = [point(10), point(10), point(10)];
+        let half: Vec<i16> = morph.interpolate(&from, 1, 2).iter().map(|p| p.x).collect();
+        assert_eq!(half, vec![6, 7, 7]);
+    }
+
+    #[test]
+    fn morph_tables_errors() {
+        let morph_points: Vec<PointRaw> = (0..5).map(point).collect();
+        let conflicting = [morph_object(1, 1, 3), morph_object(1, 3, 6)];
+        assert!(matches!(MorphTarget::create_tables(&conflicting, &morph_points), Err(Error::BadFormat(_))));
+        let truncated = [morph_object(1, 1, 3), morph_object(2, 3, 7)];
+        assert!(matches!(MorphTarget::create_tables(&truncated, &morph_points),
+            Err(Error::Truncated{expected: 6, found: 5})));
+        let extra = [morph_object(1, 1, 3), morph_object(2, 3, 5)];
+        assert!(matches!(MorphTarget::create_tables(&extra, &morph_points), Err(Error::BadFormat(_))));
+        let unused = [morph_object(1, 1, 3), morph_object(3, 3, 6)];
+        assert!(matches!(MorphTarget::create_tables(&unused, &morph_points), Err(Error::BadFormat(_))));
+        assert!(matches!(MorphTarget::create(&morph_object(3, 1, 3), &[]), Err(Error::OutOfRange{..})));
+    }
+
+    fn shape(x1: u8, y1: u8, x2: u8, y2: u8, cells: &[u8]) -> Shape {
+        let mut data = vec![x1, y1, x2, y2];
+        data.extend_from_slice(cells);
+        data.resize(Shape::SIZE, 0);
+        Shape::decode(&data)
+    }
+
+    #[test]
+    fn shape_footprint() {
+        use FootprintCell::*;
+        let footprint = shape(3, 2, 1, 0, &[1, 5, 0, 0, 2, 0]).footprint().unwrap();
+        let expected = Footprint::new(3, 2, (1, 0), vec![Occupied, Occupied, Free, Free, Entrance, Free]);
+        assert_eq!(footprint, expected);
+
+        let footprint = shape(8, 5, 9, 9, &[1; 40]).footprint().unwrap();
+        assert_eq!((footprint.width(), footprint.height(), footprint.origin()), (8, 5, (7, 4)));
+        assert_eq!(footprint.occupied().count(), 40);
+
+        assert!(matches!(shape(7, 6, 0, 0, &[]).footprint(),
+            Err(Error::OutOfRange{what: "shape cells", index: 42, len: SHAPE_CELLS_MAX})));
+    }
+
+    #[test]
+    fn object_known_bytes() {
+        let mut data = vec![0x02, 0x01, 3, 0, 4, 0, 0, 2];
+        for v in [0u32, 300, 10, 13, 20, 24] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.extend_from_slice(&[0xfe, 0xff]);
+        data.resize(44, 0);
+        data.extend_from_slice(&[5, 0]);
+        data.resize(ObjectRaw::SIZE, 0);
+        assert_eq!(ObjectRaw::SIZE, 54);
+        let object = ObjectRaw::decode(&data);
+        assert_eq!((object.flags, object.facs_num, object.pnts_num, object.morph_index), (0x0102, 3, 4, 2));
+        assert_eq!(object.coord_scale, 300);
+        assert_eq!((object.facs_ptr, object.facs_ptr_end, object.pnts_ptr, object.pnts_ptr_end), (10, 13, 20, 24));
+        assert_eq!((object.f4, object.shapes_index), (-2, 5));
+
+        let point = PointRaw::decode(&[0x01, 0x00, 0xff, 0xff, 0x00, 0x80]);
+        assert_eq!((point.x, point.y, point.z), (1, -1, i16::MIN));
+    }
+
+    #[test]
+    fn face_known_bytes() {
+        let mut data = vec![0, 0, 0xff, 0xff, 0, 0, 4, 0];
+        for v in 1..=8u32 {
+            data.extend_from_slice(&(v << 16).to_le_bytes());
+        }
+        for v in [7u16, 8, 9, 10] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.resize(FaceRaw::SIZE, 0);
+        let face = FaceRaw::decode(&data);
+        assert_eq!((face.tex_index, face.num_points), (-1, 4));
+        assert_eq!((face.point_1_u, face.point_1_v, face.point_4_v), (1 << 16, 2 << 16, 8 << 16));
+        assert_eq!((face.point_1, face.point_2, face.point_3, face.point_4), (7, 8, 9, 10));
+        assert!(face.check_points(11).is_ok());
+        assert!(matches!(face.check_points(10), Err(Error::OutOfRange{what: "face point", index: 10, len: 10})));
+    }
+}
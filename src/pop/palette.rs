@@ -0,0 +1,392 @@
+use std::path::{Path, PathBuf};
+use std::fmt::Write;
+
+use crate::pop::types::Image;
+use crate::pop::error::{Error, Result};
+use crate::pop::fs::ResourceFs;
+use crate::pop::level::read_bin;
+
+/******************************************************************************/
+
+pub const PALETTE_COLORS: usize = 256;
+// pop3 palette entry is r, g, b and an unused byte
+pub const PALETTE_STRIDE: usize = 4;
+pub const PALETTE_SIZE: usize = PALETTE_COLORS * PALETTE_STRIDE;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Palette {
+    data: Vec<u8>,
+}
+
+impl Palette {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() < PALETTE_SIZE {
+            return Err(Error::Truncated{expected: PALETTE_SIZE, found: data.len()});
+        }
+        Ok(Palette{data: data[0..PALETTE_SIZE].to_vec()})
+    }
+
+    pub fn from_rgb<I: IntoIterator<Item = [u8; 3]>>(colors: I) -> Self {
+        let mut data = vec![0u8; PALETTE_SIZE];
+        for (entry, color) in data.chunks_exact_mut(PALETTE_STRIDE).zip(colors) {
+            entry[0..3].copy_from_slice(&color);
+        }
+        Palette{data}
+    }
+
+    // pal0-X.dat or any other palette in pop3 format
+    pub fn from_fs(fs: &dyn ResourceFs, path: &Path) -> Result<Self> {
+        Self::from_bytes(&fs.read(path)?).map_err(|e| e.in_file(path))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Self::from_bytes(&read_bin(path)?).map_err(|e| e.in_file(path))
+    }
+
+    // Palette of frontend screens
+    pub fn from_fenew(fs: &dyn ResourceFs) -> Result<Self> {
+        Self::from_fs(fs, &Self::fenew_path())
+    }
+
+    pub fn fenew_path() -> PathBuf {
+        Path::new("fenew").join("fepal0.dat")
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    // Entry as stored in file
+    pub fn entry(&self, index: u8) -> [u8; 4] {
+        let i = index as usize * PALETTE_STRIDE;
+        [self.data[i], self.data[i+1], self.data[i+2], self.data[i+3]]
+    }
+
+    pub fn rgb(&self, index: u8) -> [u8; 3] {
+        let i = index as usize * PALETTE_STRIDE;
+        [self.data[i], self.data[i+1], self.data[i+2]]
+    }
+
+    pub fn rgba(&self, index: u8) -> [u8; 4] {
+        let [r, g, b] = self.rgb(index);
+        [r, g, b, 0xff]
+    }
+
+    pub fn colors(&self) -> impl Iterator<Item = [u8; 3]> + '_ {
+        (0..PALETTE_COLORS).map(|i| self.rgb(i as u8))
+    }
+
+    /*
+     * JASC-PAL text format (Paint Shop Pro)
+     */
+    pub fn to_jasc(&self) -> String {
+        let mut s = format!("JASC-PAL\r\n0100\r\n{PALETTE_COLORS}\r\n");
+        for [r, g, b] in self.colors() {
+            let _ = write!(s, "{r} {g} {b}\r\n");
+        }
+        s
+    }
+
+    pub fn from_jasc(text: &str) -> Result<Self> {
+        let mut lines = text.lines().map(|l| l.trim());
+        if lines.next() != Some("JASC-PAL") {
+            return Err(Error::BadFormat("JASC-PAL header is missing".to_string()));
+        }
+        let _version = lines.next();
+        let num: usize = lines.next()
+            .and_then(|l| l.parse().ok())
+            .ok_or_else(|| Error::BadFormat("JASC-PAL colour count is missing".to_string()))?;
+        let colors = lines.filter(|l| !l.is_empty())
+            .take(num)
+            .map(parse_rgb)
+            .collect::<Result<Vec<[u8; 3]>>>()?;
+        if colors.len() < num {
+            return Err(Error::BadFormat(format!("JASC-PAL has {} of {num} colours", colors.len())));
+        }
+        Ok(Self::from_rgb(colors))
+    }
+
+    /*
+     * GIMP palette format
+     */
+    pub fn to_gpl(&self, name: &str) -> String {
+        let mut s = format!("GIMP Palette\nName: {name}\nColumns: 16\n#\n");
+        for (i, [r, g, b]) in self.colors().enumerate() {
+            let _ = writeln!(s, "{r:3} {g:3} {b:3}\tIndex {i}");
+        }
+        s
+    }
+
+    pub fn from_gpl(text: &str) -> Result<Self> {
+        let mut lines = text.lines().map(|l| l.trim());
+        if lines.next() != Some("GIMP Palette") {
+            return Err(Error::BadFormat("GIMP Palette header is missing".to_string()));
+        }
+        let colors = lines
+            .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with("Name:") && !l.starts_with("Columns:"))
+            .take(PALETTE_COLORS)
+            .map(parse_rgb)
+            .collect::<Result<Vec<[u8; 3]>>>()?;
+        if colors.len() < PALETTE_COLORS {
+            return Err(Error::BadFormat(format!("GIMP Palette has {} of {PALETTE_COLORS} colours", colors.len())));
+        }
+        Ok(Self::from_rgb(colors))
+    }
+
+    /*
+     * Swatch is a 16x16 grid of cells, one cell per colour, row by row
+     */
+    pub fn swatch(cell_size: usize) -> Image {
+        let width = 16 * cell_size;
+        let mut image = Image::alloc(width, width);
+        for (i, v) in image.data.iter_mut().enumerate() {
+            let x = (i % width) / cell_size;
+            let y = (i / width) / cell_size;
+            *v = (y * 16 + x) as u8;
+        }
+        image
+    }
+
+    // Colours are taken from centres of swatch cells
+    pub fn from_swatch_rgba(width: usize, height: usize, rgba: &[u8]) -> Result<Self> {
+        if width < 16 || height < 16 || rgba.len() < width * height * 4 {
+            return Err(Error::BadFormat(format!("swatch {width}x{height} is too small")));
+        }
+        let colors = (0..PALETTE_COLORS).map(|i| {
+            let x = (i % 16) * width / 16 + width / 32;
+            let y = (i / 16) * height / 16 + height / 32;
+            let p = (y * width + x) * 4;
+            [rgba[p], rgba[p+1], rgba[p+2]]
+        });
+        Ok(Self::from_rgb(colors))
+    }
+}
+
+impl std::fmt::Debug for Palette {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list().entries(self.colors()).finish()
+    }
+}
+
+/******************************************************************************/
+
+pub const FADE_TABLE_WIDTH: usize = 256;
+
+/*
+ * Brightness fade levels (fade0-X.dat). Each level is 256 bytes which map
+ * palette index to palette index of the same colour with that brightness.
+ */
+#[derive(Clone)]
+pub struct FadeTable {
+    data: Vec<u8>,
+}
+
+impl FadeTable {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let levels = data.len() / FADE_TABLE_WIDTH;
+        if levels == 0 {
+            return Err(Error::Truncated{expected: FADE_TABLE_WIDTH, found: data.len()});
+        }
+        Ok(FadeTable{data: data[0..(levels * FADE_TABLE_WIDTH)].to_vec()})
+    }
+
+    pub fn from_fs(fs: &dyn ResourceFs, path: &Path) -> Result<Self> {
+        Self::from_bytes(&fs.read(path)?).map_err(|e| e.in_file(path))
+    }
+
+    pub fn levels(&self) -> usize {
+        self.data.len() / FADE_TABLE_WIDTH
+    }
+
+    // Level is clamped to the last level of table
+    pub fn fade(&self, index: u8, level: usize) -> u8 {
+        let level = level.min(self.levels() - 1);
+        self.data[level * FADE_TABLE_WIDTH + index as usize]
+    }
+}
+
+/*
+ * Translucency table (ghost0-X.dat), 256x256 bytes. Row is a source (sprite)
+ * index and column is a destination (background) index.
+ */
+#[derive(Clone)]
+pub struct BlendTable {
+    data: Vec<u8>,
+}
+
+impl BlendTable {
+    pub const SIZE: usize = PALETTE_COLORS * PALETTE_COLORS;
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() < Self::SIZE {
+            return Err(Error::Truncated{expected: Self::SIZE, found: data.len()});
+        }
+        Ok(BlendTable{data: data[0..Self::SIZE].to_vec()})
+    }
+
+    pub fn from_fs(fs: &dyn ResourceFs, path: &Path) -> Result<Self> {
+        Self::from_bytes(&fs.read(path)?).map_err(|e| e.in_file(path))
+    }
+
+    pub fn blend(&self, src: u8, dst: u8) -> u8 {
+        self.data[src as usize * PALETTE_COLORS + dst as usize]
+    }
+}
+
+/******************************************************************************/
+
+/*
+ * Palette index to palette index lookup, e.g. from colours of blue tribe to
+ * colours of other tribe. Positions of tribe colours in palettes are not known,
+ * so there are no built-in tables for tribes and a table is read from a file.
+ */
+#[derive(Clone)]
+pub struct RemapTable {
+    data: [u8; PALETTE_COLORS],
+}
+
+impl RemapTable {
+    pub fn identity() -> Self {
+        let mut data = [0u8; PALETTE_COLORS];
+        for (i, v) in data.iter_mut().enumerate() {
+            *v = i as u8;
+        }
+        RemapTable{data}
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() < PALETTE_COLORS {
+            return Err(Error::Truncated{expected: PALETTE_COLORS, found: data.len()});
+        }
+        let mut table = [0u8; PALETTE_COLORS];
+        table.copy_from_slice(&data[0..PALETTE_COLORS]);
+        Ok(RemapTable{data: table})
+    }
+
+    pub fn from_fs(fs: &dyn ResourceFs, path: &Path) -> Result<Self> {
+        Self::from_bytes(&fs.read(path)?).map_err(|e| e.in_file(path))
+    }
+
+    pub fn set_range(&mut self, from: u8, to: u8, len: usize) {
+        for i in 0..len {
+            if let Some(v) = self.data.get_mut(from as usize + i) {
+                *v = to.wrapping_add(i as u8);
+            }
+        }
+    }
+
+    pub fn remap(&self, index: u8) -> u8 {
+        self.data[index as usize]
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/******************************************************************************/
+
+fn parse_rgb(line: &str) -> Result<[u8; 3]> {
+    let mut values = line.split_whitespace().map(|v| v.parse::<u8>());
+    match (values.next(), values.next(), values.next()) {
+        (Some(Ok(r)), Some(Ok(g)), Some(Ok(b))) => Ok([r, g, b]),
+        _ => Err(Error::BadFormat(format!("bad colour {line:?}"))),
+    }
+}
+
+/******************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fade_levels() {
+        // level n maps index i to (i + n) % 256
+        let data: Vec<u8> = (0..3).flat_map(|level| (0..256).map(move |i| ((i + level) % 256) as u8)).collect();
+        let table = FadeTable::from_bytes(&data).unwrap();
+        assert_eq!(table.levels(), 3);
+        assert_eq!(table.fade(10, 0), 10);
+        assert_eq!(table.fade(10, 2), 12);
+        assert_eq!(table.fade(255, 1), 0);
+        // level is clamped to the last one
+        assert_eq!(table.fade(10, 100), 12);
+    }
+
+    #[test]
+    fn fade_truncated() {
+        assert!(matches!(FadeTable::from_bytes(&[0u8; 255]), Err(Error::Truncated{..})));
+        // incomplete level at the end is dropped
+        assert_eq!(FadeTable::from_bytes(&[0u8; 300]).unwrap().levels(), 1);
+    }
+
+    #[test]
+    fn blend_rows_and_columns() {
+        let data: Vec<u8> = (0..BlendTable::SIZE).map(|i| ((i / 256) ^ (i % 256) ^ 0x5a) as u8).collect();
+        let table = BlendTable::from_bytes(&data).unwrap();
+        assert_eq!(table.blend(0, 0), 0x5a);
+        assert_eq!(table.blend(1, 0), 0x5b);
+        assert_eq!(table.blend(0x12, 0x34), 0x12 ^ 0x34 ^ 0x5a);
+        assert_eq!(table.blend(0xff, 0xff), 0x5a);
+        assert!(matches!(BlendTable::from_bytes(&data[1..]), Err(Error::Truncated{..})));
+    }
+
+    #[test]
+    fn remap_ranges() {
+        let mut table = RemapTable::identity();
+        table.set_range(0x60, 0x68, 8);
+        for i in 0..=255u8 {
+            let expected = if (0x60..0x68).contains(&i) { i + 8 } else { i };
+            assert_eq!(table.remap(i), expected, "index {i}");
+        }
+        // range past the end of table is cut
+        table.set_range(0xfe, 0x10, 4);
+        assert_eq!(table.remap(0xfe), 0x10);
+        assert_eq!(table.remap(0xff), 0x11);
+
+        let table = RemapTable::from_bytes(table.as_bytes()).unwrap();
+        assert_eq!(table.remap(0x67), 0x6f);
+        assert!(matches!(RemapTable::from_bytes(&[0u8; 255]), Err(Error::Truncated{..})));
+    }
+
+    fn test_palette() -> Palette {
+        Palette::from_rgb((0..PALETTE_COLORS).map(|i| [i as u8, (i * 7) as u8, 255 - i as u8]))
+    }
+
+    #[test]
+    fn jasc_round_trip() {
+        let palette = test_palette();
+        let text = palette.to_jasc();
+        assert!(text.starts_with("JASC-PAL\r\n0100\r\n256\r\n0 0 255\r\n1 7 254\r\n"));
+        assert_eq!(Palette::from_jasc(&text).unwrap(), palette);
+        // colour count below 256 is allowed, other colours are black
+        let short = Palette::from_jasc("JASC-PAL\n0100\n2\n1 2 3\n4 5 6\n").unwrap();
+        assert_eq!(short.rgb(1), [4, 5, 6]);
+        assert_eq!(short.rgb(2), [0, 0, 0]);
+    }
+
+    #[test]
+    fn jasc_rejected() {
+        let text = test_palette().to_jasc();
+        let truncated: String = text.lines().take(200).map(|l| format!("{l}\n")).collect();
+        assert!(matches!(Palette::from_jasc(&truncated), Err(Error::BadFormat(_))));
+        assert!(matches!(Palette::from_jasc("GIMP Palette\n"), Err(Error::BadFormat(_))));
+        assert!(matches!(Palette::from_jasc("JASC-PAL\n0100\n1\n1 2\n"), Err(Error::BadFormat(_))));
+    }
+
+    #[test]
+    fn gpl_round_trip() {
+        let palette = test_palette();
+        let text = palette.to_gpl("test");
+        assert!(text.starts_with("GIMP Palette\nName: test\nColumns: 16\n#\n  0   0 255\tIndex 0\n"));
+        assert_eq!(Palette::from_gpl(&text).unwrap(), palette);
+    }
+
+    #[test]
+    fn gpl_rejected() {
+        let text = test_palette().to_gpl("test");
+        let truncated: String = text.lines().take(100).map(|l| format!("{l}\n")).collect();
+        assert!(matches!(Palette::from_gpl(&truncated), Err(Error::BadFormat(_))));
+        assert!(matches!(Palette::from_gpl("JASC-PAL\n"), Err(Error::BadFormat(_))));
+    }
+}
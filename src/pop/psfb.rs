@@ -1,6 +1,8 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
-use crate::pop::types::{BinDeserializer, Image, ImageStorage, ImageInfo, ImageStorageSource};
+use crate::pop::types::{BinDeserializer, BinSerializer, Image, BlendImage, PixelOp, ImageStorage, ImageInfo, ImageStorageSource, read_record};
+use crate::pop::error::{Error, Result};
+use crate::pop::palette::Palette;
 
 /******************************************************************************/
 
@@ -13,30 +15,36 @@ pub struct SpritePSFB {
 }
 
 impl SpritePSFB {
-    pub fn to_storage<S: ImageStorage>(&self, s: &mut S, data_in: &[u8]) {
+    // Malformed data (runs past the end of data or of a row) is an error
+    pub fn to_storage<S: ImageStorage>(&self, s: &mut S, data_in: &[u8]) -> Result<()> {
+        let width = self.width as usize;
         let height = self.height as usize;
+        let byte_at = |i: usize| -> Result<u8> {
+            data_in.get(i).copied().ok_or(Error::Truncated{expected: i + 1, found: data_in.len()})
+        };
         let mut source_index = 0;
         let mut height_index = 0;
-        while data_in[source_index] == 0 && height_index < height {
+        while height_index < height && byte_at(source_index)? == 0 {
             source_index += 1;
             height_index += 1;
         }
         while height_index < height {
             let mut dest_index = 0;
-            while data_in[source_index] != 0 {
-                let val: i8 = unsafe {
-                    std::mem::transmute::<u8, i8>(data_in[source_index])
-                };
+            while byte_at(source_index)? != 0 {
+                let val = byte_at(source_index)? as i8;
                 if val <= 0 {
-                    dest_index += (-val) as usize;
+                    dest_index += (-(val as isize)) as usize;
                 } else {
                     let val = val as usize;
-                    let line = {
-                        let line_start = source_index + 1;
-                        let line_end = source_index + val;
-                        &data_in[line_start..=line_end]
-                    };
-                    s.set_line(dest_index, height_index, line);
+                    let line_start = source_index + 1;
+                    let line_end = line_start + val;
+                    if line_end > data_in.len() {
+                        return Err(Error::Truncated{expected: line_end, found: data_in.len()});
+                    }
+                    if dest_index + val > width {
+                        return Err(Error::OutOfRange{what: "sprite pixel", index: dest_index + val - 1, len: width});
+                    }
+                    s.set_line(dest_index, height_index, &data_in[line_start..line_end]);
                     dest_index += val;
                     source_index += val;
                 }
@@ -45,6 +53,32 @@ impl SpritePSFB {
             source_index += 1;
             height_index += 1;
         }
+        Ok(())
+    }
+}
+
+/*
+ * Encodes rows of image. Each row is a sequence of commands terminated by 0:
+ * negative value skips transparent (0) pixels, positive value is followed by that
+ * many pixels. Transparent pixels at the end of a row are not encoded.
+ */
+fn encode_sprite(image: &Image, data: &mut Vec<u8>) {
+    const MAX_RUN: usize = 127;
+    for row in image.data.chunks(image.width).take(image.height) {
+        let end = row.iter().rposition(|v| *v != 0).map_or(0, |p| p + 1);
+        let mut i = 0;
+        while i < end {
+            let transparent = row[i] == 0;
+            let run = row[i..end].iter().take(MAX_RUN).take_while(|v| (**v == 0) == transparent).count();
+            if transparent {
+                data.push((-(run as i8)) as u8);
+            } else {
+                data.push(run as u8);
+                data.extend_from_slice(&row[i..(i + run)]);
+            }
+            i += run;
+        }
+        data.push(0);
     }
 }
 
@@ -59,19 +93,29 @@ impl ImageInfo for SpritePSFB {
 }
 
 impl BinDeserializer for SpritePSFB {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> where Self: Sized {
-        let mut buf_u16 = [0u8; 2];
-        reader.read_exact(&mut buf_u16).unwrap();
-        let width = u16::from_le_bytes(buf_u16);
-        reader.read_exact(&mut buf_u16).unwrap();
-        let height = u16::from_le_bytes(buf_u16);
-        let mut buf_u32 = [0u8; 4];
-        reader.read_exact(&mut buf_u32).unwrap();
-        let offset = u32::from_le_bytes(buf_u32);
-        Some(Self{index: 0, offset: offset as usize, width, height})
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Option<Self>> where Self: Sized {
+        let mut buf = [0u8; 8];
+        if !read_record(reader, &mut buf)? {
+            return Ok(None);
+        }
+        let width = u16::from_le_bytes([buf[0], buf[1]]);
+        let height = u16::from_le_bytes([buf[2], buf[3]]);
+        let offset = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        Ok(Some(Self{index: 0, offset: offset as usize, width, height}))
+    }
+}
+
+impl BinSerializer for SpritePSFB {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.width.to_le_bytes())?;
+        writer.write_all(&self.height.to_le_bytes())?;
+        writer.write_all(&(self.offset as u32).to_le_bytes())?;
+        Ok(())
     }
 }
 
+const PSFB_MAGIC: u32 = 0x42465350; // note(): "PSFB" in hex
+
 pub struct ContainerPSFB {
     header_size: usize,
     sprites: Vec<SpritePSFB>,
@@ -79,6 +123,34 @@ pub struct ContainerPSFB {
 }
 
 impl ContainerPSFB {
+    // Palette index 0 is transparent
+    pub fn from_images(images: &[Image]) -> Result<Self> {
+        let header_size = 8 + 8 * images.len();
+        let mut sprites = Vec::with_capacity(images.len());
+        let mut data = Vec::new();
+        for (index, image) in images.iter().enumerate() {
+            let len = u16::MAX as usize + 1;
+            if image.width >= len || image.height >= len {
+                return Err(Error::OutOfRange{what: "sprite size", index: image.width.max(image.height), len});
+            }
+            if image.data.len() < image.width * image.height {
+                return Err(Error::Truncated{expected: image.width * image.height, found: image.data.len()});
+            }
+            let offset = header_size + data.len();
+            if image.width > 0 {
+                encode_sprite(image, &mut data);
+            } else {
+                data.resize(data.len() + image.height, 0);
+            }
+            sprites.push(SpritePSFB{index, offset, width: image.width as u16, height: image.height as u16});
+        }
+        Ok(Self{header_size, sprites, data})
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        self.to_writer(writer)
+    }
+
     pub fn len(&self) -> usize {
         self.sprites.len()
     }
@@ -99,50 +171,271 @@ impl ContainerPSFB {
         self.sprites.get(index).cloned()
     }
 
-    pub fn get_storage<S: ImageStorageSource>(&self, index: usize, provider: &mut S) -> bool {
+    fn sprite_data(&self, s: &SpritePSFB) -> &[u8] {
+        // offsets are validated when container is loaded or built
+        &self.data[(s.offset - self.header_size)..]
+    }
+
+    pub fn get_storage<S: ImageStorageSource>(&self, index: usize, provider: &mut S) -> Result<bool> {
         if let Some(s) = self.sprites.get(index) {
-            let offset = s.offset - self.header_size;
             if let Some(storage) = provider.get_storage(s) {
-                s.to_storage(storage, &self.data[offset..]);
-                return true;
+                s.to_storage(storage, self.sprite_data(s)).map_err(|e| e.at(s.offset as u64))?;
+                return Ok(true);
             }
         }
-        false
+        Ok(false)
     }
 
-    pub fn get_image(&self, index: usize) -> Option<Image> {
+    pub fn get_image(&self, index: usize) -> Result<Option<Image>> {
         if let Some(s) = self.sprites.get(index) {
-            let offset = s.offset - self.header_size;
             let mut image = Image::alloc(s.width as usize, s.height as usize);
-            s.to_storage(&mut image, &self.data[offset..]);
-            return Some(image);
+            s.to_storage(&mut image, self.sprite_data(s)).map_err(|e| e.at(s.offset as u64))?;
+            return Ok(Some(image));
         }
-        None
+        Ok(None)
+    }
+
+    // Sprite pixels are passed through op, e.g. to remap tribe colours
+    pub fn get_image_op(&self, index: usize, op: PixelOp) -> Result<Option<Image>> {
+        if let Some(s) = self.sprites.get(index) {
+            let mut image = BlendImage::alloc(s.width as usize, s.height as usize, op);
+            s.to_storage(&mut image, self.sprite_data(s)).map_err(|e| e.at(s.offset as u64))?;
+            return Ok(Some(image.image));
+        }
+        Ok(None)
     }
 }
 
 impl BinDeserializer for ContainerPSFB {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> where Self: Sized {
-        let mut buf = [0u8; 4];
-        reader.read_exact(&mut buf).unwrap();
-        let marker = u32::from_le_bytes(buf);
-        if marker != 0x42465350 { // note(): "PSFB" in hex
-            return None;
-        }
-        reader.read_exact(&mut buf).unwrap();
-        let sprite_num = u32::from_le_bytes(buf) as usize;
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Option<Self>> where Self: Sized {
+        let mut buf = [0u8; 8];
+        if !read_record(reader, &mut buf)? {
+            return Ok(None);
+        }
+        let marker = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        if marker != PSFB_MAGIC {
+            return Err(Error::BadMagic{expected: PSFB_MAGIC, found: marker}.at(0));
+        }
+        let sprite_num = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]) as usize;
         let header_size = 8 + 8 * sprite_num;
         let mut sprites = Vec::new();
         for i in 0..sprite_num {
-            if let Some(mut sprite) = SpritePSFB::from_reader(reader) {
-                sprite.index = i;
-                sprites.push(sprite);
+            let sprite_offset = (8 + 8 * i) as u64;
+            match SpritePSFB::from_reader(reader) {
+                Ok(Some(mut sprite)) => {
+                    if sprite.offset < header_size {
+                        let e = Error::OutOfRange{what: "sprite offset", index: sprite.offset, len: header_size};
+                        return Err(e.at(sprite_offset));
+                    }
+                    sprite.index = i;
+                    sprites.push(sprite);
+                },
+                Ok(None) => {
+                    return Err(Error::Truncated{expected: header_size, found: sprite_offset as usize});
+                },
+                Err(e) => return Err(e.at(sprite_offset)),
             }
         }
         let mut data = Vec::new();
-        reader.read_to_end(&mut data).unwrap();
-        Some(Self{header_size, sprites, data})
+        reader.read_to_end(&mut data)?;
+        // Empty sprites may point right at the end of data
+        for sprite in &sprites {
+            let offset = sprite.offset - header_size;
+            if offset > data.len() || (offset == data.len() && sprite.height > 0) {
+                let e = Error::OutOfRange{what: "sprite offset", index: sprite.offset, len: header_size + data.len()};
+                return Err(e.at((8 + 8 * sprite.index) as u64));
+            }
+        }
+        Ok(Some(Self{header_size, sprites, data}))
+    }
+}
+
+impl BinSerializer for ContainerPSFB {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&PSFB_MAGIC.to_le_bytes())?;
+        writer.write_all(&(self.sprites.len() as u32).to_le_bytes())?;
+        SpritePSFB::to_writer_vec(&self.sprites, writer)?;
+        writer.write_all(&self.data)?;
+        Ok(())
+    }
+}
+
+/******************************************************************************/
+
+fn nearest_pal_index(pal: &Palette, color: [f32; 3]) -> u8 {
+    let mut best = (1, f32::MAX);
+    // index 0 is transparent, so it is never used for opaque pixels
+    for (i, entry) in pal.colors().enumerate().skip(1) {
+        let dr = entry[0] as f32 - color[0];
+        let dg = entry[1] as f32 - color[1];
+        let db = entry[2] as f32 - color[2];
+        let dist = dr * dr + dg * dg + db * db;
+        if dist < best.1 {
+            best = (i, dist);
+        }
     }
+    best.0 as u8
+}
+
+/*
+ * Maps RGBA pixels to indices of palette. Pixels with alpha below 128 become transparent (0). With dither set colour error
+ * is spread to neighbour opaque pixels (Floyd-Steinberg).
+ */
+pub fn quantize_rgba(width: usize, height: usize, rgba: &[u8], pal: &Palette, dither: bool) -> Image {
+    let mut image = Image::alloc(width, height);
+    let mut colors: Vec<[f32; 3]> = rgba.chunks_exact(4)
+        .take(width * height)
+        .map(|p| [p[0] as f32, p[1] as f32, p[2] as f32])
+        .collect();
+    let opaque = |i: usize| rgba.get(i * 4 + 3).is_some_and(|a| *a >= 128);
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            if i >= colors.len() || !opaque(i) {
+                continue;
+            }
+            let color = colors[i].map(|c| c.clamp(0.0, 255.0));
+            let index = nearest_pal_index(pal, color);
+            image.data[i] = index;
+            if !dither {
+                continue;
+            }
+            let p = pal.rgb(index);
+            let err = [color[0] - p[0] as f32, color[1] - p[1] as f32, color[2] - p[2] as f32];
+            let mut spread = |dx: isize, dy: usize, k: f32| {
+                let nx = x as isize + dx;
+                let ny = y + dy;
+                if nx < 0 || nx as usize >= width || ny >= height {
+                    return;
+                }
+                let n = ny * width + nx as usize;
+                if n < colors.len() && opaque(n) {
+                    for c in 0..3 {
+                        colors[n][c] += err[c] * k;
+                    }
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+    image
 }
 
 /******************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pop::types::tests::synthetic_bytes;
+    use crate::pop::palette::RemapTable;
+
+    // Container with sprites 3x2, 0x0 and 2x1, the last one is stored after an unused byte
+    fn synthetic_psfb() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&PSFB_MAGIC.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes());
+        let header_size = 8 + 8 * 3;
+        let sprites: [(u16, u16, usize); 3] = [(3, 2, 0), (0, 0, 9), (2, 1, 10)];
+        for (width, height, offset) in sprites {
+            data.extend_from_slice(&width.to_le_bytes());
+            data.extend_from_slice(&height.to_le_bytes());
+            data.extend_from_slice(&((header_size + offset) as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&[3, 1, 2, 3, 0, 0xff, 1, 9, 0]);
+        data.extend_from_slice(&[0x55, 2, 7, 8, 0]);
+        data
+    }
+
+    #[test]
+    fn container_round_trip() {
+        let data = synthetic_psfb();
+        let container = ContainerPSFB::from_reader_one(&mut &data[..]).unwrap();
+        assert_eq!(container.len(), 3);
+        let mut out = Vec::new();
+        container.to_writer(&mut out).unwrap();
+        assert_eq!(out, data);
+        assert_eq!(container.get_image(0).unwrap().unwrap().data, vec![1, 2, 3, 0, 9, 0]);
+        assert_eq!(container.get_image(2).unwrap().unwrap().data, vec![7, 8]);
+    }
+
+    // Pixels are mostly in runs, so that transparent and opaque runs longer than 127 occur
+    fn random_sprite(width: usize, height: usize, seed: u32) -> Image {
+        let noise = synthetic_bytes(width * height + 1, seed);
+        let mut image = Image::alloc(width, height);
+        let mut val = 0;
+        for (pixel, n) in image.data.iter_mut().zip(&noise) {
+            if *n < 8 {
+                val = if val == 0 { *n + 1 } else { 0 };
+            } else if val != 0 && *n > 200 {
+                val = *n;
+            }
+            *pixel = val;
+        }
+        image
+    }
+
+    fn assert_sprites_round_trip(images: &[Image]) {
+        let container = ContainerPSFB::from_images(images).unwrap();
+        let mut data = Vec::new();
+        container.write_to(&mut data).unwrap();
+        let decoded = ContainerPSFB::from_reader_one(&mut &data[..]).unwrap();
+        assert_eq!(decoded.len(), images.len());
+        for (index, image) in images.iter().enumerate() {
+            let res = decoded.get_image(index).unwrap().unwrap();
+            assert_eq!((res.width, res.height), (image.width, image.height), "sprite {index}");
+            assert_eq!(res.data, image.data, "sprite {index}");
+        }
+    }
+
+    #[test]
+    fn sprites_round_trip_random() {
+        let sizes = synthetic_bytes(64, 11);
+        let images: Vec<Image> = sizes.chunks(2).enumerate()
+            .map(|(i, n)| random_sprite(n[0] as usize + 1, n[1] as usize % 32 + 1, i as u32 + 1))
+            .collect();
+        assert_sprites_round_trip(&images);
+    }
+
+    #[test]
+    fn sprites_round_trip_edge_cases() {
+        let mut long_runs = Image::alloc(400, 3);
+        // transparent run of 300 pixels, then opaque run of 100
+        long_runs.data[300..400].fill(7);
+        // opaque run of 200 pixels with transparent end of row
+        long_runs.data[400..600].fill(9);
+        // rows 0 and 2 are fully transparent, rows 1 and 3 end with transparent pixels
+        let mut trailing = Image::alloc(5, 4);
+        trailing.data[6] = 1;
+        trailing.data[16] = 2;
+        let mut last_pixel = Image::alloc(200, 1);
+        last_pixel.data[199] = 3;
+        let images = [
+            long_runs,
+            trailing,
+            last_pixel,
+            Image::alloc(10, 5),
+            Image::alloc(0, 4),
+            Image::alloc(6, 0),
+            Image::alloc(0, 0),
+        ];
+        assert_sprites_round_trip(&images);
+    }
+
+    #[test]
+    fn sprite_remap() {
+        // pixels of remapped range 0x60..0x68 at both ends of runs, transparent pixel in between
+        let mut sprite = Image::alloc(6, 2);
+        sprite.data.copy_from_slice(&[0x60, 0x5f, 0x67, 0, 0x68, 0x61, 1, 0x63, 0, 0, 0xff, 0x66]);
+        let data = sprite.data.clone();
+        let container = ContainerPSFB::from_images(&[sprite]).unwrap();
+        let mut table = RemapTable::identity();
+        table.set_range(0x60, 0x70, 8);
+        let remapped = container.get_image_op(0, PixelOp::Remap(&table)).unwrap().unwrap();
+        assert_eq!(remapped.data, vec![0x70, 0x5f, 0x77, 0, 0x68, 0x71, 1, 0x73, 0, 0, 0xff, 0x76]);
+        let copied = container.get_image_op(0, PixelOp::Remap(&RemapTable::identity())).unwrap().unwrap();
+        assert_eq!(copied.data, data);
+    }
+}
@@ -1,51 +1,268 @@
 use std::path::Path;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write, ErrorKind};
 use std::marker::PhantomData;
 
+use crate::pop::error::{Error, Result};
+use crate::pop::fs::ResourceFs;
+use crate::pop::palette::{Palette, FadeTable, BlendTable, RemapTable, PALETTE_STRIDE};
+
 /******************************************************************************/
 
 pub trait BinDeserializer {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> where Self: Sized;
+    // Ok(None) means that reader is exhausted exactly at a record boundary
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Option<Self>> where Self: Sized;
 
-    fn from_reader_vec<R: Read>(reader: &mut R) -> Vec<Self> where Self: Sized {
+    fn from_reader_vec<R: Read>(reader: &mut R) -> Result<Vec<Self>> where Self: Sized {
+        let mut reader = OffsetReader::new(reader);
         let mut res = Vec::new();
-        while let Some(obj) = Self::from_reader(reader) {
-            res.push(obj);
+        loop {
+            let offset = reader.offset();
+            match Self::from_reader(&mut reader) {
+                Ok(Some(obj)) => res.push(obj),
+                Ok(None) => break,
+                Err(e) => return Err(e.at(offset)),
+            }
         }
-        res
+        Ok(res)
+    }
+
+    fn from_file_vec(path: &Path) -> Result<Vec<Self>> where Self: Sized {
+        let mut file = open_file(path)?;
+        Self::from_reader_vec(&mut file).map_err(|e| e.in_file(path))
+    }
+
+    fn from_file(path: &Path) -> Result<Self> where Self: Sized {
+        let mut file = open_file(path)?;
+        Self::from_reader_one(&mut file).map_err(|e| e.in_file(path))
+    }
+
+    fn from_fs_vec(fs: &dyn ResourceFs, path: &Path) -> Result<Vec<Self>> where Self: Sized {
+        let mut reader = fs.open(path)?;
+        Self::from_reader_vec(&mut reader).map_err(|e| e.in_file(path))
+    }
+
+    fn from_fs(fs: &dyn ResourceFs, path: &Path) -> Result<Self> where Self: Sized {
+        let mut reader = fs.open(path)?;
+        Self::from_reader_one(&mut reader).map_err(|e| e.in_file(path))
     }
 
-    fn from_file_vec(path: &Path) -> Vec<Self> where Self: Sized {
-        let mut file = File::options().read(true).open(path).unwrap();
-        Self::from_reader_vec(&mut file)
+    fn from_reader_one<R: Read>(reader: &mut R) -> Result<Self> where Self: Sized {
+        match Self::from_reader(reader)? {
+            Some(obj) => Ok(obj),
+            None => Err(Error::Truncated{expected: 1, found: 0}),
+        }
+    }
+}
+
+pub trait BinSerializer {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()>;
+
+    fn to_writer_vec<W: Write>(items: &[Self], writer: &mut W) -> Result<()> where Self: Sized {
+        for item in items {
+            item.to_writer(writer)?;
+        }
+        Ok(())
+    }
+
+    fn to_file_vec(items: &[Self], path: &Path) -> Result<()> where Self: Sized {
+        let mut file = create_file(path)?;
+        Self::to_writer_vec(items, &mut file).map_err(|e| e.in_file(path))
+    }
+
+    fn to_file(&self, path: &Path) -> Result<()> {
+        let mut file = create_file(path)?;
+        self.to_writer(&mut file).map_err(|e| e.in_file(path))
+    }
+}
+
+pub fn open_file(path: &Path) -> Result<File> {
+    File::options().read(true).open(path).map_err(|e| Error::from(e).in_file(path))
+}
+
+pub fn create_file(path: &Path) -> Result<File> {
+    File::create(path).map_err(|e| Error::from(e).in_file(path))
+}
+
+/*
+ * Fills the whole buffer. Returns false if reader had no data at all and error if
+ * it ended in the middle of the buffer.
+ */
+pub fn read_record<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    match filled {
+        0 if !buf.is_empty() => Ok(false),
+        n if n == buf.len() => Ok(true),
+        n => Err(Error::Truncated{expected: buf.len(), found: n}),
+    }
+}
+
+/******************************************************************************/
+
+/*
+ * Fixed size little-endian record. Layout is declared field by field with pop_record!,
+ * so decoding does not depend on host endianness or on struct layout.
+ */
+pub trait Record: Sized {
+    const SIZE: usize;
+    const FIELDS: &'static [RecordField];
+
+    // data must contain at least SIZE bytes
+    fn decode(data: &[u8]) -> Self;
+    fn encode(&self, data: &mut Vec<u8>);
+    fn field_values(&self) -> Vec<String>;
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RecordField {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize,
+}
+
+pub trait RecordValue: Sized {
+    const SIZE: usize;
+
+    fn decode_le(data: &[u8]) -> (Self, &[u8]);
+    fn encode_le(&self, data: &mut Vec<u8>);
+}
+
+macro_rules! impl_record_value {
+    ($($t:ty),*) => {
+        $(
+        impl RecordValue for $t {
+            const SIZE: usize = std::mem::size_of::<$t>();
+
+            fn decode_le(data: &[u8]) -> (Self, &[u8]) {
+                let (v, rest) = data.split_at(Self::SIZE);
+                (<$t>::from_le_bytes(v.try_into().unwrap()), rest)
+            }
+
+            fn encode_le(&self, data: &mut Vec<u8>) {
+                data.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+        )*
+    };
+}
+
+impl_record_value!(u8, i8, u16, i16, u32, i32);
+
+impl<T: RecordValue, const N: usize> RecordValue for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+
+    fn decode_le(data: &[u8]) -> (Self, &[u8]) {
+        let (v, rest) = data.split_at(Self::SIZE);
+        (std::array::from_fn(|i| T::decode_le(&v[(i * T::SIZE)..]).0), rest)
     }
 
-    fn from_file(path: &Path) -> Option<Self> where Self: Sized {
-        let mut file = File::options().read(true).open(path).unwrap();
-        Self::from_reader(&mut file)
+    fn encode_le(&self, data: &mut Vec<u8>) {
+        for v in self {
+            v.encode_le(data);
+        }
     }
 }
 
-pub fn from_reader<T, const S: usize, R: Read>(reader: &mut R) -> Option<T> where T: Copy {
-    let mut data = [0u8; S];
-    if let Ok(()) = reader.read_exact(&mut data) {
-        return Some(unsafe {
-            *(data.as_ptr() as *const T)
-        });
+macro_rules! pop_record {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::pop::types::Record for $name {
+            const SIZE: usize = 0 $(+ <$ty as $crate::pop::types::RecordValue>::SIZE)*;
+            const FIELDS: &'static [$crate::pop::types::RecordField] = &{
+                let mut fields = [$($crate::pop::types::RecordField{
+                    name: stringify!($field),
+                    offset: 0,
+                    size: <$ty as $crate::pop::types::RecordValue>::SIZE,
+                }),*];
+                let mut offset = 0;
+                let mut i = 0;
+                while i < fields.len() {
+                    fields[i].offset = offset;
+                    offset += fields[i].size;
+                    i += 1;
+                }
+                fields
+            };
+
+            fn decode(data: &[u8]) -> Self {
+                let rest = data;
+                $(let ($field, rest) = <$ty as $crate::pop::types::RecordValue>::decode_le(rest);)*
+                let _ = rest;
+                Self{$($field),*}
+            }
+
+            fn encode(&self, data: &mut Vec<u8>) {
+                $($crate::pop::types::RecordValue::encode_le(&self.$field, data);)*
+            }
+
+            fn field_values(&self) -> Vec<String> {
+                vec![$(format!("{:?}", self.$field)),*]
+            }
+        }
+    };
+}
+
+pub(crate) use pop_record;
+
+impl<T: Record> BinDeserializer for T {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Option<Self>> {
+        let mut data = vec![0u8; T::SIZE];
+        if read_record(reader, &mut data)? {
+            return Ok(Some(T::decode(&data)));
+        }
+        Ok(None)
     }
-    None
 }
 
-pub fn from_reader_vec<T, const S: usize, R: Read>(reader: &mut R) -> Vec<T> where T: Copy {
-    let mut items = Vec::new();
-    let mut data = [0u8; S];
-    while let Ok(()) = reader.read_exact(&mut data) {
-        items.push(unsafe {
-            *(data.as_ptr() as *const T)
-        });
+impl<T: Record> BinSerializer for T {
+    fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut data = Vec::with_capacity(T::SIZE);
+        self.encode(&mut data);
+        writer.write_all(&data)?;
+        Ok(())
+    }
+}
+
+/******************************************************************************/
+
+pub struct OffsetReader<R> {
+    reader: R,
+    offset: u64,
+}
+
+impl<R: Read> OffsetReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self{reader, offset: 0}
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl<R: Read> Read for OffsetReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.offset += n as u64;
+        Ok(n)
     }
-    items
 }
 
 /******************************************************************************/
@@ -60,6 +277,17 @@ pub trait ImagePos {
     fn pos_y(&self) -> isize;
 }
 
+// Mirroring of layer, layer position is not changed
+pub trait ImageFlip {
+    fn flip_horizontal(&self) -> bool {
+        false
+    }
+
+    fn flip_vertical(&self) -> bool {
+        false
+    }
+}
+
 impl<'a, T> ImageInfo for &'a T where T: ImageInfo {
     fn width(&self) -> usize {
         (*self).width()
@@ -179,16 +407,26 @@ impl ImagePos for ImageArea {
 pub struct ImageSourceComposed<C, S> {
     composer: C,
     tile: ImageTile<S>,
+    // areas of composed images in order of get_storage calls
+    placements: Vec<ImageArea>,
 }
 
 impl<C, S> ImageSourceComposed<C, S> {
     pub fn new(composer: C, image: S) -> Self {
-        Self{composer, tile: ImageTile::new(image)}
+        Self{composer, tile: ImageTile::new(image), placements: Vec::new()}
     }
 
     pub fn get_image(self) -> S {
         self.tile.get_image()
     }
+
+    pub fn placements(&self) -> &[ImageArea] {
+        &self.placements
+    }
+
+    pub fn get_image_placements(self) -> (S, Vec<ImageArea>) {
+        (self.tile.get_image(), self.placements)
+    }
 }
 
 impl<C: ImageComposer, M: ImageInfo + ImageStorage> ImageStorageSource for ImageSourceComposed<C, M> {
@@ -196,6 +434,8 @@ impl<C: ImageComposer, M: ImageInfo + ImageStorage> ImageStorageSource for Image
 
     fn get_storage<I: ImageInfo>(&mut self, info: &I) -> Option<&mut Self::StorageType> {
         if self.composer.compose(&mut self.tile, info) {
+            let tile = &self.tile;
+            self.placements.push(ImageArea::new(tile.tile_width, tile.tile_height, tile.start_x as isize, tile.start_y as isize));
             Some(&mut self.tile)
         } else {
             None
@@ -222,8 +462,8 @@ impl<C, A, R, I> AllocatorEqual<ImageSourceComposed<R, I>> for ImageAllocatorCom
     fn alloc_equal<U: ImageInfo>(&self, info: &U, num: usize) -> ImageSourceComposed<R, I> {
         let c = self.composer.alloc_equal(info, num);
         let image = self.image.alloc(&c);
-        let tile = ImageTile{image, start_x: 0, start_y: 0, tile_width: 0, tile_height: 0};
-        ImageSourceComposed{composer: c, tile}
+        let tile = ImageTile::new(image);
+        ImageSourceComposed{composer: c, tile, placements: Vec::new()}
     }
 }
 
@@ -232,8 +472,8 @@ impl<C, A, R, I> AllocatorIter<ImageSourceComposed<R, I>> for ImageAllocatorComp
     fn alloc_iter<'a, U: ImageInfo + 'a, T: Iterator<Item=&'a U>>(&'a self, iter: &mut T) -> ImageSourceComposed<R, I> {
         let c = self.composer.alloc_iter(iter);
         let image = self.image.alloc(&c);
-        let tile = ImageTile{image, start_x: 0, start_y: 0, tile_width: 0, tile_height: 0};
-        ImageSourceComposed{composer: c, tile}
+        let tile = ImageTile::new(image);
+        ImageSourceComposed{composer: c, tile, placements: Vec::new()}
     }
 }
 
@@ -303,8 +543,8 @@ pub fn image_allocator_2d(width: usize) -> ImageAllocatorComposed<usize, (), Ima
     ImageAllocatorComposed::new(width, ())
 }
 
-pub fn pal_image_allocator_1d_vertical(pal: &[u8])
-    -> ImageAllocatorComposed<(), &[u8], ImageComposer1D<ComposeVertical>> {
+pub fn pal_image_allocator_1d_vertical(pal: &Palette)
+    -> ImageAllocatorComposed<(), &Palette, ImageComposer1D<ComposeVertical>> {
     ImageAllocatorComposed::new((), pal)
 }
 
@@ -419,6 +659,8 @@ impl ImagePos for (isize, isize) {
     }
 }
 
+impl ImageFlip for (isize, isize) {}
+
 pub trait LayerComposer {
     type ComposerResult: ImageInfo;
 
@@ -513,7 +755,7 @@ pub struct LayeredStorageSource<'a, II, IMM, C> {
 
 impl<'a, II, M, M1, C> LayeredStorageSource<'a, II, ImageTile<&'a mut M1>, C>
     where
-        M: ImagePos,
+        M: ImagePos + ImageFlip,
         II: 'a + Iterator<Item=M>,
         M1: 'a + ImageInfo + ImageStorage,
         C: 'a + LayerComposer {
@@ -524,11 +766,16 @@ impl<'a, II, M, M1, C> LayeredStorageSource<'a, II, ImageTile<&'a mut M1>, C>
         let img = ImageTile::new(storage.get_storage(&area).unwrap());
         Self{area, img_iter: imgs, img, last_cords: (0, 0), composer}
     }
+
+    // Changes how following layers are drawn over previous ones
+    pub fn set_pixel_op<'t>(&mut self, op: PixelOp<'t>) where M1: PixelOpTarget<'t> {
+        self.img.image.set_pixel_op(op);
+    }
 }
 
 impl<'a, II, M, M1, C> ImageStorageSource for LayeredStorageSource<'a, II, ImageTile<&'a mut M1>, C>
     where
-        M: ImagePos,
+        M: ImagePos + ImageFlip,
         II: 'a + Iterator<Item=M>,
         M1: 'a + ImageInfo + ImageStorage,
         C: 'a + LayerComposer<ComposerResult=ImageArea> {
@@ -540,6 +787,9 @@ impl<'a, II, M, M1, C> ImageStorageSource for LayeredStorageSource<'a, II, Image
             let composed_image = ImageArea::from_image_and_pos(&info, &i);
             self.last_cords = self.composer.get_start(&self.area, &composed_image);
             self.img.move_tile(self.last_cords.0, self.last_cords.1);
+            self.img.tile_width = info.width();
+            self.img.tile_height = info.height();
+            self.img.set_flip(i.flip_horizontal(), i.flip_vertical());
             Some(&mut self.img)
         } else {
             None
@@ -555,11 +805,27 @@ pub struct ImageTile<I> {
     start_y: usize,
     tile_width: usize,
     tile_height: usize,
+    flip_horizontal: bool,
+    flip_vertical: bool,
 }
 
 impl<I> ImageTile<I> {
     pub fn new(image: I) -> Self {
-        Self{image, start_x: 0, start_y: 0, tile_width: 0, tile_height: 0}
+        Self{image, start_x: 0, start_y: 0, tile_width: 0, tile_height: 0, flip_horizontal: false, flip_vertical: false}
+    }
+
+    // Pixels are mirrored inside of tile, tile size must be set
+    pub fn set_flip(&mut self, horizontal: bool, vertical: bool) {
+        self.flip_horizontal = horizontal;
+        self.flip_vertical = vertical;
+    }
+
+    fn flip_y(&self, y: usize) -> usize {
+        if self.flip_vertical {
+            self.tile_height.saturating_sub(y + 1)
+        } else {
+            y
+        }
     }
 
     fn get_image(self) -> I {
@@ -584,11 +850,21 @@ impl<I: ImageInfo + ImageStorage> ImageInfo for ImageTile<I> {
 
 impl<I: ImageInfo + ImageStorage> ImageStorage for ImageTile<I> {
     fn set_pixel(&mut self, x: usize, y: usize, val: u8) {
+        let x = if self.flip_horizontal { self.tile_width.saturating_sub(x + 1) } else { x };
+        let y = self.flip_y(y);
         self.image.set_pixel(self.start_x + x, self.start_y + y, val);
     }
 
     fn set_line(&mut self, x: usize, y: usize, data: &[u8]) {
-        self.image.set_line(self.start_x + x, self.start_y + y, data);
+        let y = self.flip_y(y);
+        if self.flip_horizontal {
+            let end = self.tile_width.saturating_sub(x);
+            let data = &data[0..data.len().min(end)];
+            let line: Vec<u8> = data.iter().rev().copied().collect();
+            self.image.set_line(self.start_x + end - line.len(), self.start_y + y, &line);
+        } else {
+            self.image.set_line(self.start_x + x, self.start_y + y, data);
+        }
     }
 
     fn set_image(&mut self, data: &[u8]) {
@@ -727,19 +1003,122 @@ impl ImageAllocator<Image> for () {
 
 /******************************************************************************/
 
+/*
+ * Operation applied to a pixel written over an existing one
+ */
+#[derive(Copy, Clone)]
+pub enum PixelOp<'a> {
+    Copy,
+    Fade(&'a FadeTable, usize),
+    Blend(&'a BlendTable),
+    Remap(&'a RemapTable),
+}
+
+impl<'a> PixelOp<'a> {
+    pub fn apply(&self, src: u8, dst: u8) -> u8 {
+        match self {
+            PixelOp::Copy => src,
+            PixelOp::Fade(table, level) => table.fade(src, *level),
+            PixelOp::Blend(table) => table.blend(src, dst),
+            PixelOp::Remap(table) => table.remap(src),
+        }
+    }
+}
+
+pub trait PixelOpTarget<'a> {
+    fn set_pixel_op(&mut self, op: PixelOp<'a>);
+}
+
+impl<'a, T> PixelOpTarget<'a> for &mut T where T: PixelOpTarget<'a> {
+    fn set_pixel_op(&mut self, op: PixelOp<'a>) {
+        (**self).set_pixel_op(op)
+    }
+}
+
+impl<'a, T> PixelOpTarget<'a> for ImageTile<T> where T: PixelOpTarget<'a> {
+    fn set_pixel_op(&mut self, op: PixelOp<'a>) {
+        self.image.set_pixel_op(op)
+    }
+}
+
+// Indexed image which applies PixelOp to all written pixels
+pub struct BlendImage<'a> {
+    pub image: Image,
+    pub op: PixelOp<'a>,
+}
+
+impl<'a> BlendImage<'a> {
+    pub fn alloc(width: usize, height: usize, op: PixelOp<'a>) -> Self {
+        Self{image: Image::alloc(width, height), op}
+    }
+}
+
+impl<'a> ImageInfo for BlendImage<'a> {
+    fn width(&self) -> usize {
+        self.image.width
+    }
+
+    fn height(&self) -> usize {
+        self.image.height
+    }
+}
+
+impl<'a> ImageStorage for BlendImage<'a> {
+    fn set_pixel(&mut self, x: usize, y: usize, val: u8) {
+        let index = self.image.index(x, y);
+        if let Some(dst) = self.image.data.get_mut(index) {
+            *dst = self.op.apply(val, *dst);
+        }
+    }
+
+    fn set_line(&mut self, x: usize, y: usize, data: &[u8]) {
+        let index = self.image.index(x, y);
+        let to_copy = data.len().min(self.image.width.saturating_sub(x));
+        let end = (index + to_copy).min(self.image.data.len());
+        for (dst, src) in self.image.data[index..end].iter_mut().zip(data) {
+            *dst = self.op.apply(*src, *dst);
+        }
+    }
+
+    fn set_image(&mut self, data: &[u8]) {
+        for (dst, src) in self.image.data.iter_mut().zip(data) {
+            *dst = self.op.apply(*src, *dst);
+        }
+    }
+}
+
+impl<'a> PixelOpTarget<'a> for BlendImage<'a> {
+    fn set_pixel_op(&mut self, op: PixelOp<'a>) {
+        self.op = op;
+    }
+}
+
+impl<'a> ImageAllocator<BlendImage<'a>> for PixelOp<'a> {
+    fn alloc<I: ImageInfo>(&self, info: &I) -> BlendImage<'a> {
+        BlendImage::alloc(info.width(), info.height(), *self)
+    }
+}
+
+pub fn blend_image_allocator_2d(width: usize, op: PixelOp)
+    -> ImageAllocatorComposed<usize, PixelOp, ImageComposer2D> {
+    ImageAllocatorComposed::new(width, op)
+}
+
+/******************************************************************************/
+
 pub struct PalImage<'a> {
-    pub pal: &'a[u8],
+    pub pal: &'a Palette,
     pub width: usize,
     pub height: usize,
     pub data: Vec<u8>,
 }
 
 impl<'a> PalImage<'a> {
-    pub fn new(pal: &'a[u8], width: usize, height: usize, data: Vec<u8>) -> Self {
+    pub fn new(pal: &'a Palette, width: usize, height: usize, data: Vec<u8>) -> Self {
         Self{pal, width, height, data}
     }
 
-    pub fn alloc(pal: &'a[u8], width: usize, height: usize) -> Self {
+    pub fn alloc(pal: &'a Palette, width: usize, height: usize) -> Self {
         Self{pal, width, height, data: vec![0u8; width * height * 4]}
     }
 
@@ -761,16 +1140,14 @@ impl<'a> ImageInfo for PalImage<'a> {
 impl<'a> ImageStorage for PalImage<'a> {
     fn set_pixel(&mut self, x: usize, y: usize, val: u8) {
         let index = self.index(x, y);
-        let pal_index = val as usize * 4;
-        self.data[index..(index+4)].copy_from_slice(&self.pal[pal_index..(pal_index+4)]);
+        self.data[index..(index+4)].copy_from_slice(&self.pal.entry(val));
     }
 
     fn set_line(&mut self, x: usize, y: usize, data: &[u8]) {
         let index = self.index(x, y);
         for (i, val) in data.iter().enumerate() {
-            let data_index = index + i * 4;
-            let pal_index = *val as usize * 4;
-            self.data[data_index..(data_index+4)].copy_from_slice(&self.pal[pal_index..(pal_index+4)]);
+            let data_index = index + i * PALETTE_STRIDE;
+            self.data[data_index..(data_index+4)].copy_from_slice(&self.pal.entry(*val));
         }
     }
 
@@ -780,7 +1157,7 @@ impl<'a> ImageStorage for PalImage<'a> {
     }
 }
 
-impl<'a> ImageAllocator<PalImage<'a>> for &'a[u8] {
+impl<'a> ImageAllocator<PalImage<'a>> for &'a Palette {
     fn alloc<I: ImageInfo>(&self, info: &I) -> PalImage<'a> {
         PalImage::alloc(self, info.width(), info.height())
     }
@@ -795,3 +1172,72 @@ impl<M, F> ImageAllocator<M> for F where F: Fn(&dyn ImageInfo) -> M {
 }
 
 /******************************************************************************/
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Deterministic pseudo random bytes (xorshift) for synthetic fixtures
+    pub(crate) fn synthetic_bytes(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 24) as u8
+        }).collect()
+    }
+
+    // Decoding and encoding of a few records must give the same bytes
+    pub(crate) fn assert_record_round_trip<T: Record>() {
+        let data = synthetic_bytes(T::SIZE * 3, T::SIZE as u32);
+        let records = T::from_reader_vec(&mut &data[..]).unwrap();
+        assert_eq!(records.len(), 3);
+        let mut out = Vec::new();
+        T::to_writer_vec(&records, &mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn record_truncated() {
+        let data = synthetic_bytes(5, 1);
+        let mut reader = &data[..];
+        assert!(matches!(read_record(&mut reader, &mut [0u8; 8]), Err(Error::Truncated{expected: 8, found: 5})));
+        assert!(!read_record(&mut reader, &mut [0u8; 8]).unwrap());
+    }
+
+    // Blend table which gives src ^ dst
+    fn xor_table() -> BlendTable {
+        let data: Vec<u8> = (0..BlendTable::SIZE).map(|i| ((i / 256) ^ (i % 256)) as u8).collect();
+        BlendTable::from_bytes(&data).unwrap()
+    }
+
+    #[test]
+    fn blend_image_set_image() {
+        let table = xor_table();
+        let mut image = BlendImage::alloc(3, 1, PixelOp::Blend(&table));
+        image.image.data.copy_from_slice(&[1, 2, 3]);
+        image.set_image(&[3, 3, 3]);
+        assert_eq!(image.image.data, vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn blend_layer_in_tile() {
+        let table = xor_table();
+        let mut image = BlendImage::alloc(4, 3, PixelOp::Copy);
+        image.set_image(&[0x10; 12]);
+        let mut tile = ImageTile::new(image);
+        tile.tile_width = 2;
+        tile.tile_height = 2;
+        tile.move_tile(1, 1);
+        tile.set_pixel_op(PixelOp::Blend(&table));
+        tile.set_image(&[1, 2, 3, 4]);
+        tile.set_flip(true, false);
+        tile.set_line(0, 0, &[0x20]);
+        assert_eq!(tile.get_image().image.data, vec![
+            0x10, 0x10, 0x10, 0x10,
+            0x10, 0x11, 0x32, 0x10,
+            0x10, 0x13, 0x14, 0x10,
+        ]);
+    }
+}
@@ -1,42 +1,497 @@
-use std::io::Read;
-use core::mem::size_of;
+use std::fmt;
 
-use crate::pop::types::{BinDeserializer, from_reader};
+use crate::pop::types::pop_record;
+use crate::pop::level::flip_row;
 
 /******************************************************************************/
 
+pop_record! {
+    #[derive(Debug, Copy, Clone)]
+    pub struct UnitRaw {
+        pub unit_type: u8,
+        pub unit_class: u8,
+        tribe_index: u8,
+        loc_x: u16,
+        loc_y: u16,
+        f1: u32,
+        f2: u16,
+        f3: u16,
+        fd: [u8; 40],
+    }
+}
+
+/******************************************************************************/
+
+pop_record! {
+    #[derive(Debug, Copy, Clone)]
+    pub struct TribeConfigRaw {
+        pub data: [u8; 16],
+    }
+}
+
+/******************************************************************************/
+
+/*
+ * Spell names by bit index in TribeConfig::spells. Order is the one of spell
+ * model numbers in PopScript (M_SPELL_BLAST = 1 to M_SPELL_BLOODLUST = 20),
+ * bit of a spell is taken to be its model number.
+ */
+pub const SPELL_NAMES: [&str; 21] = [
+    "None", "Blast", "Lightning", "Tornado", "Swarm", "Invisibility", "Hypnotise",
+    "Firestorm", "GhostArmy", "Erosion", "Swamp", "LandBridge", "AngelOfDeath",
+    "Earthquake", "Flatten", "Volcano", "Armageddon", "Shield", "Convert",
+    "Teleport", "Bloodlust",
+];
+
+/*
+ * Tribe settings. Spells and buildings masks are stored in a level file, while
+ * computer/alliance flags are stored in a level header. Bytes 8..16 of the level
+ * record are not decoded and kept as is.
+ *
+ * Starting mana is not decoded: no field of this record is known to hold it and
+ * there is no format reference which places it here, so it may be one of the
+ * unknown bytes or not be stored per level at all.
+ */
 #[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
-pub struct UnitRaw {
-    pub unit_type: u8,
-    pub unit_class: u8,
-    tribe_index: u8,
-    loc_x: u16,
-    loc_y: u16,
-    f1: u32,
-    f2: u16,
-    f3: u16,
-    fd: [u8; 40],
+pub struct TribeConfig {
+    pub tribe: Tribe,
+    pub is_computer: bool,
+    pub allies: u8,
+    pub spells: u32,
+    pub buildings: u32,
+    pub unknown: [u8; 8],
 }
 
-impl BinDeserializer for UnitRaw {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> {
-        from_reader::<UnitRaw, {size_of::<UnitRaw>()}, R>(reader)
+impl TribeConfig {
+    pub fn from_raw(index: u8, raw: &TribeConfigRaw, is_computer: bool, allies: u8) -> Self {
+        let d = &raw.data;
+        let mut unknown = [0u8; 8];
+        unknown.copy_from_slice(&d[8..16]);
+        TribeConfig {
+            tribe: Tribe::from_index(index),
+            is_computer,
+            allies,
+            spells: u32::from_le_bytes([d[0], d[1], d[2], d[3]]),
+            buildings: u32::from_le_bytes([d[4], d[5], d[6], d[7]]),
+            unknown,
+        }
+    }
+
+    pub fn to_raw(&self) -> TribeConfigRaw {
+        let mut data = [0u8; 16];
+        data[0..4].copy_from_slice(&self.spells.to_le_bytes());
+        data[4..8].copy_from_slice(&self.buildings.to_le_bytes());
+        data[8..16].copy_from_slice(&self.unknown);
+        TribeConfigRaw{data}
+    }
+
+    pub fn is_spell_allowed(&self, spell: usize) -> bool {
+        spell < 32 && (self.spells & (1 << spell)) != 0
+    }
+
+    pub fn set_spell_allowed(&mut self, spell: usize, allowed: bool) {
+        set_bit(&mut self.spells, spell, allowed);
+    }
+
+    pub fn is_building_allowed(&self, building: usize) -> bool {
+        building < 32 && (self.buildings & (1 << building)) != 0
+    }
+
+    pub fn set_building_allowed(&mut self, building: usize, allowed: bool) {
+        set_bit(&mut self.buildings, building, allowed);
+    }
+
+    pub fn is_ally(&self, tribe_index: usize) -> bool {
+        tribe_index < 8 && (self.allies & (1 << tribe_index)) != 0
+    }
+
+    pub fn spell_names(&self) -> Vec<&'static str> {
+        (0..SPELL_NAMES.len()).filter(|s| self.is_spell_allowed(*s)).map(|s| SPELL_NAMES[s]).collect()
+    }
+}
+
+fn set_bit(mask: &mut u32, bit: usize, val: bool) {
+    if bit >= 32 {
+        return;
+    }
+    if val {
+        *mask |= 1 << bit;
+    } else {
+        *mask &= !(1 << bit);
     }
 }
 
 /******************************************************************************/
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Tribe {
+    Blue,
+    Red,
+    Yellow,
+    Green,
+    Neutral(u8),
+}
+
+impl Tribe {
+    pub fn from_index(index: u8) -> Self {
+        match index {
+            0 => Tribe::Blue,
+            1 => Tribe::Red,
+            2 => Tribe::Yellow,
+            3 => Tribe::Green,
+            v => Tribe::Neutral(v),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnitClass {
+    Person,
+    Building,
+    Creature,
+    Vehicle,
+    Scenery,
+    General,
+    Effect,
+    Shot,
+    Shape,
+    Internal,
+    Spell,
+    Unknown(u8),
+}
+
+impl UnitClass {
+    pub fn from_raw(v: u8) -> Self {
+        match v {
+            1 => UnitClass::Person,
+            2 => UnitClass::Building,
+            3 => UnitClass::Creature,
+            4 => UnitClass::Vehicle,
+            5 => UnitClass::Scenery,
+            6 => UnitClass::General,
+            7 => UnitClass::Effect,
+            8 => UnitClass::Shot,
+            9 => UnitClass::Shape,
+            10 => UnitClass::Internal,
+            11 => UnitClass::Spell,
+            v => UnitClass::Unknown(v),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PersonType {
+    Wild,
+    Brave,
+    Warrior,
+    Preacher,
+    Spy,
+    Firewarrior,
+    Shaman,
+    AngelOfDeath,
+    Unknown(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BuildingType {
+    Hut1,
+    Hut2,
+    Hut3,
+    DrumTower,
+    Temple,
+    SpyTraining,
+    WarriorTraining,
+    FirewarriorTraining,
+    Reconversion,
+    WallPiece,
+    Gate,
+    BoatHut1,
+    BoatHut2,
+    AirshipHut1,
+    AirshipHut2,
+    Unknown(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CreatureType {
+    Bear,
+    Buffalo,
+    Wolf,
+    Eagle,
+    Rabbit,
+    Beaver,
+    Fish,
+    Unknown(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VehicleType {
+    Boat1,
+    Boat2,
+    Airship1,
+    Airship2,
+    Unknown(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SceneryType {
+    Tree1,
+    Tree2,
+    Tree3,
+    Tree4,
+    Tree5,
+    Tree6,
+    Plant1,
+    Plant2,
+    StoneHead,
+    Fire,
+    WoodPile,
+    Pillar,
+    Rock,
+    Portal,
+    Island,
+    Bridge,
+    DormantTree,
+    TopLevelScenery,
+    SubLevelScenery,
+    Unknown(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GeneralType {
+    Light,
+    Discovery,
+    DebugStatic,
+    DebugFlying,
+    DebugFlag,
+    Trigger,
+    VehicleConstruction,
+    Unknown(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnitSubtype {
+    Person(PersonType),
+    Building(BuildingType),
+    Creature(CreatureType),
+    Vehicle(VehicleType),
+    Scenery(SceneryType),
+    General(GeneralType),
+    Other(u8),
+}
+
+impl UnitSubtype {
+    pub fn from_raw(class: UnitClass, v: u8) -> Self {
+        match class {
+            UnitClass::Person => UnitSubtype::Person(match v {
+                1 => PersonType::Wild,
+                2 => PersonType::Brave,
+                3 => PersonType::Warrior,
+                4 => PersonType::Preacher,
+                5 => PersonType::Spy,
+                6 => PersonType::Firewarrior,
+                7 => PersonType::Shaman,
+                8 => PersonType::AngelOfDeath,
+                v => PersonType::Unknown(v),
+            }),
+            UnitClass::Building => UnitSubtype::Building(match v {
+                1 => BuildingType::Hut1,
+                2 => BuildingType::Hut2,
+                3 => BuildingType::Hut3,
+                4 => BuildingType::DrumTower,
+                5 => BuildingType::Temple,
+                6 => BuildingType::SpyTraining,
+                7 => BuildingType::WarriorTraining,
+                8 => BuildingType::FirewarriorTraining,
+                9 => BuildingType::Reconversion,
+                10 => BuildingType::WallPiece,
+                11 => BuildingType::Gate,
+                13 => BuildingType::BoatHut1,
+                14 => BuildingType::BoatHut2,
+                15 => BuildingType::AirshipHut1,
+                16 => BuildingType::AirshipHut2,
+                v => BuildingType::Unknown(v),
+            }),
+            UnitClass::Creature => UnitSubtype::Creature(match v {
+                1 => CreatureType::Bear,
+                2 => CreatureType::Buffalo,
+                3 => CreatureType::Wolf,
+                4 => CreatureType::Eagle,
+                5 => CreatureType::Rabbit,
+                6 => CreatureType::Beaver,
+                7 => CreatureType::Fish,
+                v => CreatureType::Unknown(v),
+            }),
+            UnitClass::Vehicle => UnitSubtype::Vehicle(match v {
+                1 => VehicleType::Boat1,
+                2 => VehicleType::Boat2,
+                3 => VehicleType::Airship1,
+                4 => VehicleType::Airship2,
+                v => VehicleType::Unknown(v),
+            }),
+            UnitClass::Scenery => UnitSubtype::Scenery(match v {
+                1 => SceneryType::Tree1,
+                2 => SceneryType::Tree2,
+                3 => SceneryType::Tree3,
+                4 => SceneryType::Tree4,
+                5 => SceneryType::Tree5,
+                6 => SceneryType::Tree6,
+                7 => SceneryType::Plant1,
+                8 => SceneryType::Plant2,
+                9 => SceneryType::StoneHead,
+                10 => SceneryType::Fire,
+                11 => SceneryType::WoodPile,
+                12 => SceneryType::Pillar,
+                13 => SceneryType::Rock,
+                14 => SceneryType::Portal,
+                15 => SceneryType::Island,
+                16 => SceneryType::Bridge,
+                17 => SceneryType::DormantTree,
+                18 => SceneryType::TopLevelScenery,
+                19 => SceneryType::SubLevelScenery,
+                v => SceneryType::Unknown(v),
+            }),
+            UnitClass::General => UnitSubtype::General(match v {
+                1 => GeneralType::Light,
+                2 => GeneralType::Discovery,
+                3 => GeneralType::DebugStatic,
+                4 => GeneralType::DebugFlying,
+                5 => GeneralType::DebugFlag,
+                6 => GeneralType::Trigger,
+                7 => GeneralType::VehicleConstruction,
+                v => GeneralType::Unknown(v),
+            }),
+            _ => UnitSubtype::Other(v),
+        }
+    }
+}
+
+impl fmt::Display for UnitSubtype {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnitSubtype::Person(v) => write!(f, "{v:?}"),
+            UnitSubtype::Building(v) => write!(f, "{v:?}"),
+            UnitSubtype::Creature(v) => write!(f, "{v:?}"),
+            UnitSubtype::Vehicle(v) => write!(f, "{v:?}"),
+            UnitSubtype::Scenery(v) => write!(f, "{v:?}"),
+            UnitSubtype::General(v) => write!(f, "{v:?}"),
+            UnitSubtype::Other(v) => write!(f, "{v:?}"),
+        }
+    }
+}
+
+/******************************************************************************/
+
+// Size of a landscape cell in map coordinates
+const CELL_SIZE_SHIFT: u16 = 9;
+const LAND_SIZE: usize = 128;
+
 #[derive(Debug, Copy, Clone)]
-#[repr(C, packed)]
-pub struct TribeConfigRaw {
-    pub data: [u8; 16],
+pub struct Unit {
+    pub class: UnitClass,
+    pub subtype: UnitSubtype,
+    pub tribe: Tribe,
+    /*
+     * Cell in Landscape orientation, i.e. landscape.height[cell_y][cell_x]
+     * is the height under the unit.
+     */
+    pub cell_x: usize,
+    pub cell_y: usize,
+    pub loc_x: u16,
+    pub loc_y: u16,
+    // 0..2047 is a full turn
+    pub angle: u16,
+    pub extra: [u8; 40],
+    pub raw: UnitRaw,
 }
 
-impl BinDeserializer for TribeConfigRaw {
-    fn from_reader<R: Read>(reader: &mut R) -> Option<Self> {
-        from_reader::<TribeConfigRaw, {size_of::<TribeConfigRaw>()}, R>(reader)
+impl Unit {
+    pub fn from_raw(raw: &UnitRaw) -> Self {
+        let class = UnitClass::from_raw(raw.unit_class);
+        let subtype = UnitSubtype::from_raw(class, raw.unit_type);
+        let cell_x = (raw.loc_x >> CELL_SIZE_SHIFT) as usize;
+        let cell_y = flip_row(LAND_SIZE, (raw.loc_y >> CELL_SIZE_SHIFT) as usize);
+        Unit {
+            class,
+            subtype,
+            tribe: Tribe::from_index(raw.tribe_index),
+            cell_x,
+            cell_y,
+            loc_x: raw.loc_x,
+            loc_y: raw.loc_y,
+            angle: raw.f2 & 0x7ff,
+            extra: raw.fd,
+            raw: *raw,
+        }
+    }
+
+    pub fn from_raw_vec(raw: &[UnitRaw]) -> Vec<Self> {
+        raw.iter().filter(|u| u.unit_class != 0).map(Self::from_raw).collect()
     }
 }
 
 /******************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pop::types::tests::assert_record_round_trip;
+    use crate::pop::types::Record;
+
+    #[test]
+    fn records_round_trip() {
+        assert_record_round_trip::<UnitRaw>();
+        assert_record_round_trip::<TribeConfigRaw>();
+    }
+
+    #[test]
+    fn tribe_config_bits() {
+        let mut data = [0u8; 16];
+        // Blast, Invisibility and Bloodlust
+        data[0..4].copy_from_slice(&[0x22, 0x00, 0x10, 0x00]);
+        // buildings 0 and 9
+        data[4..8].copy_from_slice(&[0x01, 0x02, 0x00, 0x00]);
+        data[8..16].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let raw = TribeConfigRaw{data};
+        let mut config = TribeConfig::from_raw(2, &raw, true, 0b0101);
+        assert_eq!(config.tribe, Tribe::Yellow);
+        assert!(config.is_computer);
+        assert_eq!(config.spells, 0x0010_0022);
+        assert_eq!(config.buildings, 0x0000_0201);
+        assert_eq!(config.unknown, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(config.spell_names(), vec!["Blast", "Invisibility", "Bloodlust"]);
+        assert!(config.is_building_allowed(0) && config.is_building_allowed(9));
+        assert!(!config.is_building_allowed(1) && !config.is_building_allowed(40));
+        assert!(config.is_ally(0) && config.is_ally(2) && !config.is_ally(1));
+        assert_eq!(config.to_raw().data, data);
+
+        config.set_spell_allowed(5, false);
+        config.set_spell_allowed(2, true);
+        config.set_building_allowed(31, true);
+        config.set_building_allowed(0, false);
+        let mut expected = data;
+        expected[0] = 0x06;
+        expected[4] = 0x00;
+        expected[7] = 0x80;
+        assert_eq!(config.to_raw().data, expected);
+        assert_eq!(config.spell_names(), vec!["Blast", "Lightning", "Bloodlust"]);
+    }
+
+    #[test]
+    fn unit_known_bytes() {
+        let mut data = vec![2, 1, 1, 0x00, 0x0a, 0x00, 0x14, 0xef, 0xbe, 0xad, 0xde, 0x01, 0x08, 0x34, 0x12];
+        data.extend((0..40).map(|i| i as u8 + 100));
+        assert_eq!(data.len(), UnitRaw::SIZE);
+        let raw = UnitRaw::decode(&data);
+        assert_eq!((raw.unit_type, raw.unit_class, raw.tribe_index), (2, 1, 1));
+        assert_eq!((raw.loc_x, raw.loc_y), (0x0a00, 0x1400));
+        assert_eq!((raw.f1, raw.f2, raw.f3), (0xdeadbeef, 0x0801, 0x1234));
+        assert_eq!((raw.fd[0], raw.fd[39]), (100, 139));
+        let unit = Unit::from_raw(&raw);
+        assert_eq!(unit.class, UnitClass::Person);
+        assert_eq!(unit.tribe, Tribe::Red);
+        // row 10 of file is row 117 of landscape
+        assert_eq!((unit.cell_x, unit.cell_y), (5, 117));
+        assert_eq!(unit.angle, 1);
+    }
+}
@@ -0,0 +1,66 @@
+use std::io::Write;
+
+use crate::pop::error::Result;
+use crate::pop::objects::Object3D;
+
+/******************************************************************************/
+
+pub const MATERIAL_TEXTURED: &str = "bl320";
+pub const MATERIAL_UNTEXTURED: &str = "untextured";
+
+/*
+ * Wavefront OBJ export. Vertices are not shared between faces since every face
+ * has its own UVs, faces without texture use a plain material.
+ */
+pub fn write_obj<W: Write>(writer: &mut W, name: &str, object: &Object3D, mtl_name: &str) -> Result<()> {
+    let scale = object.model_scale();
+    writeln!(writer, "mtllib {mtl_name}")?;
+    writeln!(writer, "o {name}")?;
+    let mut vertex_index = 1;
+    let mut uv_index = 1;
+    let mut material = "";
+    for face in object.iter_face() {
+        for v in face.vertices() {
+            writeln!(writer, "v {} {} {}", v.x * scale, v.y * scale, v.z * scale)?;
+        }
+        let face_material = if face.has_texture() { MATERIAL_TEXTURED } else { MATERIAL_UNTEXTURED };
+        if face_material != material {
+            material = face_material;
+            writeln!(writer, "usemtl {material}")?;
+        }
+        let mut line = String::from("f");
+        for i in 0..face.vertex_num {
+            match face.atlas_uv(i) {
+                Some((u, v)) => {
+                    // OBJ v axis goes up
+                    writeln!(writer, "vt {} {}", u, 1.0 - v)?;
+                    line.push_str(&format!(" {}/{}", vertex_index + i, uv_index));
+                    uv_index += 1;
+                },
+                None => line.push_str(&format!(" {}", vertex_index + i)),
+            }
+        }
+        writeln!(writer, "{line}")?;
+        vertex_index += face.vertex_num;
+    }
+    Ok(())
+}
+
+pub fn write_mtl<W: Write>(writer: &mut W, texture_name: &str) -> Result<()> {
+    writeln!(writer, "newmtl {MATERIAL_TEXTURED}")?;
+    writeln!(writer, "Ka 1 1 1")?;
+    writeln!(writer, "Kd 1 1 1")?;
+    writeln!(writer, "Ks 0 0 0")?;
+    writeln!(writer, "illum 1")?;
+    writeln!(writer, "map_Kd {texture_name}")?;
+    writeln!(writer, "map_d {texture_name}")?;
+    writeln!(writer)?;
+    writeln!(writer, "newmtl {MATERIAL_UNTEXTURED}")?;
+    writeln!(writer, "Ka 1 1 0")?;
+    writeln!(writer, "Kd 1 1 0")?;
+    writeln!(writer, "Ks 0 0 0")?;
+    writeln!(writer, "illum 1")?;
+    Ok(())
+}
+
+/******************************************************************************/